@@ -1,3 +1,7 @@
+#[cfg(not(feature = "navarro"))]
+fn main() {}
+
+#[cfg(feature = "navarro")]
 fn main() {
     let src = [
         "external/repair/array.c",
@@ -16,4 +20,7 @@ fn main() {
         .flag("-Wno-sign-compare");
 
     build.compile("repair");
+
+    // cc emits rerun-if-env-changed lines, which disables cargo's default change detection
+    println!("cargo:rerun-if-changed=external/repair");
 }
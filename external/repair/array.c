@@ -29,16 +29,16 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include <stdlib.h>
 #include "array.h"
 
-int insertArray (Tarray *A, int pair)
+Tint insertArray (Tarray *A, Tint pair)
 
    { if (A->size == A->maxsize)
 	{ if (A->maxsize == 0)
 	     { A->maxsize = A->minsize;
-	       A->pairs = malloc (A->maxsize * sizeof(int));
+	       A->pairs = malloc (A->maxsize * sizeof(Tint));
 	     }
 	  else
 	     { A->maxsize /= A->factor;
-	       A->pairs = realloc (A->pairs, A->maxsize * sizeof(int));
+	       A->pairs = realloc (A->pairs, A->maxsize * sizeof(Tint));
 	     }
 	}
      A->pairs[A->size] = pair;
@@ -56,11 +56,11 @@ void deleteArray (Tarray *A)
      else if ((A->size < A->maxsize * A->factor * A->factor) && 
 	      (A->maxsize * A->factor >= A->minsize))
 	{ A->maxsize *= A->factor;
-	  A->pairs = realloc (A->pairs, A->maxsize * sizeof(int));
+	  A->pairs = realloc (A->pairs, A->maxsize * sizeof(Tint));
 	}
    }
 
-Tarray createArray (float factor, int minsize)
+Tarray createArray (float factor, Tint minsize)
 
    { Tarray A;
      A.pairs = NULL;
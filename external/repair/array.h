@@ -32,20 +32,20 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include "basics.h"
 
 typedef struct
-   { int *pairs; // offsets to C
-     int maxsize;  
-     int size;
+   { Tint *pairs; // offsets to C
+     Tint maxsize;  
+     Tint size;
      float factor;
-     int minsize;
+     Tint minsize;
    } Tarray;
 
 // contents can be accessed as A.pairs[0..A.size-1]
 
-int insertArray (Tarray *A, int pair); // inserts pair in A, returns pos
+Tint insertArray (Tarray *A, Tint pair); // inserts pair in A, returns pos
 
 void deleteArray (Tarray *A); // deletes last cell in A
 
-Tarray createArray (float factor, int minsize); // creates empty array
+Tarray createArray (float factor, Tint minsize); // creates empty array
 
 void destroyArray (Tarray *A); // destroys A
   
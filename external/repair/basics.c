@@ -26,18 +26,30 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 
 #include <stdlib.h>
 #include <stdio.h>
+#include <string.h>
+#include <limits.h>
+#include "basics.h"
 
-int NullFreq = 1 << (8*sizeof(int)-1);
+#undef malloc  // the real ones are needed below
+#undef realloc
+
+Tint NullFreq = LLONG_MIN;
+
+_Thread_local jmp_buf *oomHandler = NULL;
+
+static void outOfMemory (const char *what)
+
+  { if (oomHandler != NULL) longjmp(*oomHandler,1);
+    fprintf(stderr,"Error: %s failed\n",what);
+    exit(1);
+  }
 
 void *myMalloc (long long n)
 
   { void *p;
     if (n == 0) return NULL;
     p = (void*)malloc(n);
-    if (p == NULL)
-       { fprintf(stderr,"Error: malloc failed\n");
-	 exit(1);
-       }
+    if (p == NULL) outOfMemory("malloc");
     return p;
   }
 
@@ -46,13 +58,29 @@ void *myRealloc (void *p, long long n)
   { if (n == 0) { free(p); return NULL; }
     if (p == NULL) return myMalloc(n);
     p = (void*)realloc(p,n);
-    if (p == NULL)
-       { fprintf(stderr,"Error: realloc failed\n");
-	 exit(1);
-       }
+    if (p == NULL) outOfMemory("realloc");
     return p;
   }
 
+void writeBuffer (Tbuffer *B, const void *src, size_t n)
+
+  { if (B->size + n > B->capacity)
+       { size_t cap = B->capacity ? B->capacity : 1024;
+	 while (cap < B->size + n) cap <<= 1;
+	 B->data = myRealloc(B->data,cap);
+	 B->capacity = cap;
+       }
+    memcpy(B->data + B->size,src,n);
+    B->size += n;
+  }
+
+void freeBuffer (Tbuffer *B)
+
+  { if (B->data != NULL) free(B->data);
+    B->data = NULL;
+    B->size = B->capacity = 0;
+  }
+
 int blog (int x)
 
    { int l=0;
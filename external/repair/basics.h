@@ -27,17 +27,38 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #ifndef BASICSINCLUDED
 #define BASICSINCLUDED
 
+#include <stddef.h>
+#include <setjmp.h>
+
 void *myMalloc (long long n); // safe malloc/realloc
 void *myRealloc (void *p, long long n);
 
+extern _Thread_local jmp_buf *oomHandler;
+                // if set, allocation failures longjmp here instead of
+                // exiting the process. per thread, as each run sets its own
+
+#define REPAIR_OK 0
+#define REPAIR_OUT_OF_MEMORY 1
+
 #define malloc(n) myMalloc(n)
 #define realloc(p,n) myRealloc(p,n)
 
+typedef long long Tint; // positions, symbols and counts. 64 bits, so
+                        // texts over 2^31 symbols can be indexed
+
 typedef struct
-  { int left,right;
+  { Tint left,right;
   } Tpair;
 
-extern int NullFreq;
+extern Tint NullFreq;
+
+typedef struct
+  { char *data;
+    size_t size,capacity;
+  } Tbuffer; // growable output buffer
+
+void writeBuffer (Tbuffer *B, const void *src, size_t n); // appends n bytes
+void freeBuffer (Tbuffer *B); // releases the buffer contents
 
 int blog (int x); // bits to represent x
 
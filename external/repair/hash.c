@@ -34,16 +34,21 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include <stdlib.h>
 #include "hash.h"
 
-extern float factor; // load factor
-
 typedef unsigned long long relong;
 #define LPRIME ((relong)767865341467865341)
 #define PRIME 2013686449
 
-int searchHash (Thash H, Tpair p) // returns id 
+static Tint hashPair (Thash H, Tpair p) // initial position of p in H
+			// both components are 64 bits, so they are mixed
+			// instead of concatenated
+
+  { relong u = (relong)p.left * LPRIME + (relong)p.right;
+    return ((LPRIME*u) >> 16) & H.maxpos;
+  }
+
+Tint searchHash (Thash H, Tpair p) // returns id 
 
-  { relong u = ((relong)p.left)<<(8*sizeof(int)) | (relong)p.right;
-    int k = ((LPRIME*u) >> (8*sizeof(int))) & H.maxpos;
+  { Tint k = hashPair(H,p);
     Trecord *recs = H.Rec->records;
     while (H.table[k] != -1) 
       {	if ((H.table[k] >= 0) && 
@@ -54,50 +59,49 @@ int searchHash (Thash H, Tpair p) // returns id
     return H.table[k];
   }
 
-void deleteHash (Thash *H, int id) // deletes H->Rec[id].pair from hash
+void deleteHash (Thash *H, Tint id) // deletes H->Rec[id].pair from hash
 
   { Trecord *rec = H->Rec->records;
     H->table[rec[id].kpos] = -2;
     H->used--;
   }
 
-Thash createHash (int maxpos, Trarray *Rec)
+Thash createHash (Tint maxpos, Trarray *Rec)
 				// creates new empty hash table
 
   { Thash H;
-    int i;
+    Tint i;
 	// upgrade maxpos to the next value of the form (1<<smth)-1
     while (maxpos & (maxpos-1)) maxpos &= maxpos-1;
     maxpos = (maxpos-1)<<1 | 1;  // avoids overflow if maxpos = 1<<31
     H.maxpos = maxpos;
     H.used = 0;
-    H.table = (void*)malloc((1+maxpos)*sizeof(int));
+    H.table = (void*)malloc((1+maxpos)*sizeof(Tint));
     for (i=0;i<=maxpos;i++) H.table[i] = -1;
     H.Rec = Rec;
     return H;
   }
   
-static int finsertHash (Thash H, Tpair p) 
+static Tint finsertHash (Thash H, Tpair p) 
 			// inserts w/o resizing, assumes there is space
 			// does not update used field
 			// note can reuse marked deletions
 
-  { relong u = ((relong)p.left)<<(8*sizeof(int)) | (relong)p.right;
-    int k = ((LPRIME*u) >> (8*sizeof(int))) & H.maxpos;
+  { Tint k = hashPair(H,p);
     while (H.table[k] >= 0) k = (k+1) & H.maxpos;
     return k;
   }
 
-void insertHash (Thash *H, int id) // inserts H->Rec[id].pair in hash 
+void insertHash (Thash *H, Tint id) // inserts H->Rec[id].pair in hash 
 				  // assumes key is not present
 				  // sets ptr from Rec to hash as well
 
-  { int k;
+  { Tint k;
     Trecord *rec = H->Rec->records;
-    if (H->used > H->maxpos * factor) // resize
+    if (H->used > H->maxpos * H->Rec->factor) // resize
 	{ Thash newH = createHash((H->maxpos<<1)|1,H->Rec);
-	  int i;
-	  int *tab = H->table;
+	  Tint i;
+	  Tint *tab = H->table;
 	  for (i=0;i<=H->maxpos;i++)
 	      if (tab[i] >= 0) // also removes marked deletions
 		 { k = finsertHash (newH,rec[tab[i]].pair);
@@ -122,7 +126,7 @@ void destroyHash (Thash *H)
     H->used = 0;
   }
  
-void hashRepos (Thash *H, int id)
+void hashRepos (Thash *H, Tint id)
 
   { Trecord *rec = H->Rec->records;
     H->table[rec[id].kpos] = id;
@@ -33,25 +33,25 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include "records.h"
 
 typedef struct
-  { int *table;
-    int maxpos; // of the form (1<<smth)-1
-    int used;
+  { Tint *table;
+    Tint maxpos; // of the form (1<<smth)-1
+    Tint used;
     Trarray *Rec; // records
   } Thash;
 
-Thash createHash (int maxpos, Trarray *Rec); 
+Thash createHash (Tint maxpos, Trarray *Rec); 
 					// creates new empty hash table
 
 void destroyHash (Thash *H); // destroys hash table, not heap nor list
 
-void insertHash (Thash *H, int id); // inserts H->Rec[id].pair in hash 
+void insertHash (Thash *H, Tint id); // inserts H->Rec[id].pair in hash 
 				   // assumes it is not already there
 				   // sets ptr from Rec to hash as well
 
-void deleteHash (Thash *H, int id); // deletes H->Rec[id].pair from hash
+void deleteHash (Thash *H, Tint id); // deletes H->Rec[id].pair from hash
 
-int searchHash (Thash H, Tpair p); // returns id, -1 if not existing
+Tint searchHash (Thash H, Tpair p); // returns id, -1 if not existing
 
-void hashRepos (Thash *H, int id); // repositions pair
+void hashRepos (Thash *H, Tint id); // repositions pair
 
 #endif
@@ -32,12 +32,12 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 
 static int PRNH = 0;
 
-Theap createHeap (int u, Trarray *Rec, float factor, int minsize) 
+Theap createHeap (Tint u, Trarray *Rec, float factor, Tint minsize) 
 				// creates new empty heap
 				// minsize, factor: space/time tradeoffs
 
   { Theap H;
-    int i;
+    Tint i;
     H.sqrtu = 2;
     while (H.sqrtu * H.sqrtu < u) H.sqrtu++;
     H.infreq = (void*)malloc(H.sqrtu * sizeof(Tarray));
@@ -58,7 +58,7 @@ Theap createHeap (int u, Trarray *Rec, float factor, int minsize)
   
 void destroyHeap (Theap *H) // destroys H
 
-  { int i;
+  { Tint i;
     Thfreq *l,*n;
     for (i=1;i<H->sqrtu;i++) destroyArray(&H->infreq[i]);
     free (H->infreq); H->infreq = NULL;
@@ -68,9 +68,9 @@ void destroyHeap (Theap *H) // destroys H
     H->sqrtu = 0;
   }
 
-static void move (Tarray A, int i, int j, Trecord *rec)
+static void move (Tarray A, Tint i, Tint j, Trecord *rec)
 
-  { int id = A.pairs[j];
+  { Tint id = A.pairs[j];
     A.pairs[i] = id;
     rec[id].hpos = i;
   }
@@ -79,14 +79,14 @@ void prnH (Theap *H)
 
   { Thfreq *f;
     static int X = 0;
-    int prevf = 1<<30;
-    int fp = H->largest;
+    Tint prevf = 1<<30;
+    Tint fp = H->largest;
     if (fp == -1) return;
     X++;
     printf ("Heap %i = \n",X);
     while (fp != -1)
        { f = &H->ff[fp];
-         printf ("freq=%i, elems=%i\n",f->freq,f->elems);
+         printf ("freq=%lli, elems=%lli\n",f->freq,f->elems);
 	 if (prevf <= f->freq)
 	    { fp++; }
 	 prevf = f->freq;
@@ -94,14 +94,14 @@ void prnH (Theap *H)
        }
   }
 
-void incFreq (Theap *H, int id) // inc freq of pair Rec[id]
+void incFreq (Theap *H, Tint id) // inc freq of pair Rec[id]
 
   { Trecord *rec = H->Rec->records;
-    int freq = rec[id].freq++;
-    int hpos = rec[id].hpos;
+    Tint freq = rec[id].freq++;
+    Tint hpos = rec[id].hpos;
     Thnode *p;
     Thfreq *f,*lf;
-    int fp,lfp;
+    Tint fp,lfp;
 if (PRNH) prnH(H);
     if (freq >= H->sqrtu) // high freq part, hpos is a ptr within freq
        { p = &H->freq[hpos];
@@ -190,14 +190,14 @@ if (PRNH) prnH(H);
        }
   }
 
-void decFreq (Theap *H, int id) // dec freq of pair Rec[id]
+void decFreq (Theap *H, Tint id) // dec freq of pair Rec[id]
 
   { Trecord *rec = H->Rec->records;
-    int freq = rec[id].freq--;
-    int hpos = rec[id].hpos;
+    Tint freq = rec[id].freq--;
+    Tint hpos = rec[id].hpos;
     Thnode *p;
     Thfreq *f,*sf;
-    int fp,sfp;
+    Tint fp,sfp;
 if (PRNH) prnH(H);
     if (freq > H->sqrtu) // high freq part
        { p = &H->freq[hpos];
@@ -277,20 +277,20 @@ if (PRNH) prnH(H);
        }
   }
 
-void insertHeap (Theap *H, int id)  // with freq 1
+void insertHeap (Theap *H, Tint id)  // with freq 1
 
   { Trecord *rec = H->Rec->records;
     rec[id].hpos = insertArray (&H->infreq[1],id);
     rec[id].freq = 1;
   }
 
-int extractMax (Theap *H)
+Tint extractMax (Theap *H)
 
   { Trecord *rec = H->Rec->records;
-    int ret;
+    Tint ret;
     Thnode *p;
     Thfreq *f;
-    int fp;
+    Tint fp;
 if (PRNH) prnH(H);
     if ((H->max == H->sqrtu) && (H->largest == -1)) H->max--;
     if (H->max < H->sqrtu)
@@ -326,7 +326,7 @@ void purgeHeap (Theap *H)
 			// their freq cannot grow after a repair turn
 
   { Trecord *rec = H->Rec->records;
-    int i,id;
+    Tint i,id;
     for (i=0;i<H->infreq[1].size;i++)
 	{ id = H->infreq[1].pairs[i];
 	  removeRecord (H->Rec,id);
@@ -334,7 +334,7 @@ void purgeHeap (Theap *H)
     destroyArray(&H->infreq[1]);
   }
 
-void heapRepos (Theap *H, int id) // repositions pair
+void heapRepos (Theap *H, Tint id) // repositions pair
 
   { Trecord *rec = H->Rec->records;
     if (rec[id].freq < H->sqrtu) 
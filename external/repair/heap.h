@@ -39,46 +39,46 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include "records.h"
 
 typedef struct 
-  { int freq;
-    int elems; // a pointer within freq array
-    int larger,smaller; // pointers within ff array
+  { Tint freq;
+    Tint elems; // a pointer within freq array
+    Tint larger,smaller; // pointers within ff array
   } Thfreq;
 
 typedef struct 
-  { int id;
-    int prev,next; // actually pointers within freq array
-    int fnode; // ptr to its freq node (ptr to ff)
+  { Tint id;
+    Tint prev,next; // actually pointers within freq array
+    Tint fnode; // ptr to its freq node (ptr to ff)
   } Thnode;
 
 typedef struct
   { Thnode *freq; // space for all frequent nodes is preallocated, sqrt(u)
-    int freef; // ptr to free list in freq
+    Tint freef; // ptr to free list in freq
     Thfreq *ff; // space for all frequencies of frequent nodes prealloc idem
-    int freeff; // ptr to free list in ff
-    int smallest,largest; // list of frequent ones (ptrs in ff)
+    Tint freeff; // ptr to free list in ff
+    Tint smallest,largest; // list of frequent ones (ptrs in ff)
     Tarray *infreq; // vectors for infrequent ones
-    int sqrtu;
-    int max;  // max freq heap used
+    Tint sqrtu;
+    Tint max;  // max freq heap used
     Trarray *Rec; // records
   } Theap;
 
-Theap createHeap (int u, Trarray *Rec, float factor, int minsize); 
+Theap createHeap (Tint u, Trarray *Rec, float factor, Tint minsize); 
 				// creates new empty heap
 				// 0<factor<1: occupancy factor
 				// sqrt(u)*max(minsize,n/factor) integers
 
 void destroyHeap (Theap *H); // destroys H
 
-void incFreq (Theap *H, int id); // inc freq of pair Rec[id]
+void incFreq (Theap *H, Tint id); // inc freq of pair Rec[id]
 
-void decFreq (Theap *H, int id); // dec freq of pair Rec[id]
+void decFreq (Theap *H, Tint id); // dec freq of pair Rec[id]
 
-void insertHeap (Theap *H, int id);  // with freq 1
+void insertHeap (Theap *H, Tint id);  // with freq 1
 
-int extractMax (Theap *H);
+Tint extractMax (Theap *H);
 
 void purgeHeap (Theap *H); // remove elems with freq 1
 
-void heapRepos (Theap *H, int id); // repositions pair
+void heapRepos (Theap *H, Tint id); // repositions pair
 
 #endif
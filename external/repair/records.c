@@ -29,9 +29,9 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include <stdlib.h>
 #include "records.h"
 
-int insertRecord (Trarray *Rec, Tpair pair)
+Tint insertRecord (Trarray *Rec, Tpair pair)
 
-   { int id;
+   { Tint id;
      Trecord *rec;
      if (Rec->size == Rec->maxsize)
 	{ if (Rec->maxsize == 0)
@@ -66,7 +66,7 @@ void deleteRecord (Trarray *Rec)
 	}
    }
 
-Trarray createRecords (float factor, int minsize)
+Trarray createRecords (float factor, Tint minsize)
 
    { Trarray Rec;
      Rec.records = NULL;
@@ -99,7 +99,7 @@ void destroyRecords (Trarray *Rec)
      Rec->List = NULL;
    }
      
-void removeRecord (Trarray *Rec, int id) // delete record, freq <= 1
+void removeRecord (Trarray *Rec, Tint id) // delete record, freq <= 1
 				       // due to freq 0 or purgue (freq 1)
 				       // already deleted from heap
 
@@ -32,23 +32,23 @@ Chile. Blanco Encalada 2120, Santiago, Chile. gnavarro@dcc.uchile.cl
 #include "basics.h"
 
 typedef struct
-   { int prev,next;
+   { Tint prev,next;
    } Tlist; // list of prev next equal char
 
 typedef struct
    { Tpair pair; // pair content
-     int freq; // frequency
-     int cpos; // 1st position in C
-     int hpos; // position in heap
-     int kpos; // position in hash
+     Tint freq; // frequency
+     Tint cpos; // 1st position in C
+     Tint hpos; // position in heap
+     Tint kpos; // position in hash
    } Trecord;
 
 typedef struct
    { Trecord *records; 
-     int maxsize;  
-     int size;
+     Tint maxsize;  
+     Tint size;
      float factor;
-     int minsize;
+     Tint minsize;
      void *Hash;  // Thash *
      void *Heap; // Theap *
      void *List; // Tlist *
@@ -59,20 +59,20 @@ typedef struct
 
 // contents can be accessed as Rec.records[0..Rec.size-1]
 
-int insertRecord (Trarray *Rec, Tpair pair); 
+Tint insertRecord (Trarray *Rec, Tpair pair); 
 			// inserts pair in Rec, returns id, links to/from
 			// Hash and Heap, not List. sets freq = 1
 
 void deleteRecord (Trarray *Rec); // deletes last cell in Rec
 
-Trarray createRecords (float factor, int minsize); // creates empty array
+Trarray createRecords (float factor, Tint minsize); // creates empty array
 
 void assocRecords (Trarray *Rec, void *Hash, void *Heap, void *List); 
 						// associates structures
 
 void destroyRecords (Trarray *Rec); // destroys Rec
   
-void removeRecord (Trarray *Rec, int id);// delete record, freq <= 1
+void removeRecord (Trarray *Rec, Tint id);// delete record, freq <= 1
                                        // due to freq 0 or purgue (freq 1)
                                        // already deleted from heap
 
@@ -34,374 +34,402 @@ int PRNL = 0;  // print progress on text scan
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
-#include <sys/stat.h>
-#include <sys/types.h>
-#include <unistd.h>
 
 #include "basics.h"
 #include "hash.h"
 #include "heap.h"
 #include "records.h"
 
-float factor = 0.50; // 1/extra space overhead; set closer to 1 for smaller and
-                     // slower execution
-int minsize = 256; // to avoid many reallocs at small sizes, should be ok as is
+typedef struct {
+  float factor;  // 1/extra space overhead; set closer to 1 for smaller and
+                 // slower execution
+  Tint minsize;  // to avoid many reallocs at small sizes
+  Tint u;        // |text| and later current |C| with gaps
+  Tint *C;       // compressed text
+  Tint c;        // real |C|
+  Tint alph;     // max used terminal symbol
+  Tint n;        // |R|
+  Tlist *L;      // |L| = c;
+  Thash Hash;    // hash table of pairs
+  Theap Heap;    // special heap of pairs
+  Trarray Rec;   // records
+  int ints;      // terminals are the input integers 0..alph-1, there is no map
+  int chars[256];
+  char map[256];
+} Trepair; // the whole state of one repair run, so runs can be concurrent
+
+// fills C with the chars of text, renamed to 0..alph-1 by order of appearance
+
+void loadChars(Trepair *S, const unsigned char *text, Tint len)
 
-int u; // |text| and later current |C| with gaps
-
-int *C; // compressed text
-
-int c; // real |C|
-
-int alph; // max used terminal symbol
-
-int n; // |R|
-
-Tlist *L; // |L| = c;
-
-Thash Hash; // hash table of pairs
-
-Theap Heap; // special heap of pairs
+{
+  Tint i;
+  S->c = S->u = len;
+  S->C = (void *)malloc(S->u * sizeof(Tint));
+  S->alph = 0;
+  for (i = 0; i < 256; i++)
+    S->chars[i] = -1;
+  for (i = 0; i < S->u; i++) {
+    unsigned char x = text[i];
+    if (S->chars[x] == -1)
+      S->chars[x] = S->alph++;
+    S->C[i] = S->chars[x];
+  }
+  for (i = 0; i < 256; i++)
+    if (S->chars[i] != -1)
+      S->map[S->chars[i]] = i;
+}
 
-Trarray Rec; // records
+// fills C with the ints of text, which are kept as they are.
+// as in irepair, alph is one more than the largest of them
 
-int chars[256];
+void loadInts(Trepair *S, const unsigned int *text, Tint len)
 
-char map[256];
+{
+  Tint i;
+  S->c = S->u = len;
+  S->C = (void *)malloc(S->u * sizeof(Tint));
+  S->alph = 0;
+  for (i = 0; i < S->u; i++) {
+    S->C[i] = text[i];
+    if (S->C[i] >= S->alph)
+      S->alph = S->C[i] + 1;
+  }
+}
 
-void prepare(char *text, int len)
+void prepare(Trepair *S)
 
 {
-  int i, id;
+  Tint i, id;
   Tpair pair;
-  c = u = len;
-  C = (void *)malloc(u * sizeof(int));
-  alph = 0;
-  for (i = 0; i < 256; i++)
-    chars[i] = -1;
-  for (i = 0; i < u; i++) {
-    unsigned char x = text[i];
-    if (chars[x] == -1)
-      chars[x] = alph++;
-    C[i] = chars[x];
-  }
-  free(text);
-  for (i = 0; i < 256; i++)
-    if (chars[i] != -1)
-      map[chars[i]] = i;
-  n = alph;
-  Rec = createRecords(factor, minsize);
-  Heap = createHeap(u, &Rec, factor, minsize);
-  Hash = createHash(256 * 256, &Rec);
-  L = (void *)malloc(u * sizeof(Tlist));
-  assocRecords(&Rec, &Hash, &Heap, L);
-  for (i = 0; i < c - 1; i++) {
-    pair.left = C[i];
-    pair.right = C[i + 1];
-    id = searchHash(Hash, pair);
+  S->n = S->alph;
+  S->Rec = createRecords(S->factor, S->minsize);
+  S->Heap = createHeap(S->u, &S->Rec, S->factor, S->minsize);
+  S->Hash = createHash(256 * 256, &S->Rec);
+  S->L = (void *)malloc(S->u * sizeof(Tlist));
+  assocRecords(&S->Rec, &S->Hash, &S->Heap, S->L);
+  for (i = 0; i < S->c - 1; i++) {
+    pair.left = S->C[i];
+    pair.right = S->C[i + 1];
+    id = searchHash(S->Hash, pair);
     if (id == -1) // new pair, insert
     {
-      id = insertRecord(&Rec, pair);
-      L[i].next = -1;
+      id = insertRecord(&S->Rec, pair);
+      S->L[i].next = -1;
     } else {
-      L[i].next = Rec.records[id].cpos;
-      L[L[i].next].prev = i;
-      incFreq(&Heap, id);
+      S->L[i].next = S->Rec.records[id].cpos;
+      S->L[S->L[i].next].prev = i;
+      incFreq(&S->Heap, id);
     }
-    L[i].prev = -id - 1;
-    Rec.records[id].cpos = i;
+    S->L[i].prev = -id - 1;
+    S->Rec.records[id].cpos = i;
     if (PRNL && (i % 10000 == 0))
-      printf("Processed %i chars\n", i);
+      printf("Processed %lli chars\n", i);
   }
-  L[i].prev = NullFreq;
-  L[i].next = -1;
-  purgeHeap(&Heap);
+  S->L[i].prev = NullFreq;
+  S->L[i].next = -1;
+  purgeHeap(&S->Heap);
 }
 
-void prnSym(int c) {
-  if (c < alph)
-    printf("%c", map[c]);
+void prnSym(Trepair *S, Tint sym) {
+  if (sym < S->alph && !S->ints)
+    printf("%c", S->map[sym]);
   else
-    printf("%i", c);
+    printf("%lli", sym);
 }
 
-void prnC(void)
+void prnC(Trepair *S)
 
 {
-  int i = 0;
-  printf("C[1..%i] = ", c);
-  while (i < u) {
-    prnSym(C[i]);
+  Tint i = 0;
+  printf("C[1..%lli] = ", S->c);
+  while (i < S->u) {
+    prnSym(S, S->C[i]);
     printf(" ");
     i++;
-    if ((i < u) && (C[i] < 0))
-      i = -C[i] - 1;
+    if ((i < S->u) && (S->C[i] < 0))
+      i = -S->C[i] - 1;
   }
   printf("\n\n");
 }
 
-void prnRec(void)
+void prnRec(Trepair *S)
 
 {
-  int i;
+  Tint i;
   printf("Active pairs:\n");
-  for (i = 0; i < Rec.size; i++) {
+  for (i = 0; i < S->Rec.size; i++) {
     printf("\t(");
-    prnSym(Rec.records[i].pair.left);
+    prnSym(S, S->Rec.records[i].pair.left);
     printf(",");
-    prnSym(Rec.records[i].pair.right);
-    printf("), %i occs\n", Rec.records[i].freq);
+    prnSym(S, S->Rec.records[i].pair.right);
+    printf("), %lli occs\n", S->Rec.records[i].freq);
   }
   printf("\n");
 }
 
-// to avoid another list to access the sparse C we thread it using the
+// to avoid another list to access the sparse S->C we thread it using the
 // empty space. if next cell of an active cell is negative, it is
 // (minus) a ptr to the next occ. idem previous cell to previous occ,
 // except that next ptr dominates over prev ptr if they must be in
 // the same cell. but in this case one can find prev in O(1) anyway.
 
-int repair(FILE *R)
+void repair(Trepair *S, Tbuffer *R)
 
 {
-  int oid, id, cpos;
+  Tint oid, id, cpos;
   Trecord *rec, *orec;
   Tpair pair;
-  if (fwrite(&alph, sizeof(int), 1, R) != 1)
-    return -1;
-  if (fwrite(map, sizeof(char), alph, R) != alph)
-    return -1;
+  writeBuffer(R, &S->alph, sizeof(Tint));
+  if (!S->ints)
+    writeBuffer(R, S->map, S->alph * sizeof(char));
   if (PRNC)
-    prnC();
-  while (n + 1 > 0) {
+    prnC(S);
+  while (S->n + 1 > 0) {
     if (PRNR)
-      prnRec();
-    oid = extractMax(&Heap);
+      prnRec(S);
+    oid = extractMax(&S->Heap);
     if (oid == -1)
       break; // the end!!
-    orec = &Rec.records[oid];
+    orec = &S->Rec.records[oid];
     cpos = orec->cpos;
-    if (fwrite(&orec->pair, sizeof(Tpair), 1, R) != 1)
-      return -1;
+    writeBuffer(R, &orec->pair, sizeof(Tpair));
     if (PRNP) {
-      printf("Chosen pair %i = (", n);
-      prnSym(orec->pair.left);
+      printf("Chosen pair %lli = (", S->n);
+      prnSym(S, orec->pair.left);
       printf(",");
-      prnSym(orec->pair.right);
-      printf(") (%i occs)\n", orec->freq);
+      prnSym(S, orec->pair.right);
+      printf(") (%lli occs)\n", orec->freq);
     }
     while (cpos != -1) {
-      int ant, sgte, ssgte;
-      // replacing bc->e in abcd, b = cpos, c = sgte, d = ssgte
-      if (C[cpos + 1] < 0)
-        sgte = -C[cpos + 1] - 1;
+      Tint ant, sgte, ssgte;
+      // replacing bc->e in abcd, b = cpos, S->c = sgte, d = ssgte
+      if (S->C[cpos + 1] < 0)
+        sgte = -S->C[cpos + 1] - 1;
       else
         sgte = cpos + 1;
-      if ((sgte + 1 < u) && (C[sgte + 1] < 0))
-        ssgte = -C[sgte + 1] - 1;
+      if ((sgte + 1 < S->u) && (S->C[sgte + 1] < 0))
+        ssgte = -S->C[sgte + 1] - 1;
       else
         ssgte = sgte + 1;
-      // remove bc from L
-      if (L[cpos].next != -1)
-        L[L[cpos].next].prev = -oid - 1;
-      orec->cpos = L[cpos].next;
-      if (ssgte != u) // there is ssgte
+      // remove bc from S->L
+      if (S->L[cpos].next != -1)
+        S->L[S->L[cpos].next].prev = -oid - 1;
+      orec->cpos = S->L[cpos].next;
+      if (ssgte != S->u) // there is ssgte
       {               // remove occ of cd
-        pair.left = C[sgte];
-        pair.right = C[ssgte];
-        id = searchHash(Hash, pair);
+        pair.left = S->C[sgte];
+        pair.right = S->C[ssgte];
+        id = searchHash(S->Hash, pair);
         if (id != -1) // may not exist if purgeHeap'd
         {
           if (id != oid)
-            decFreq(&Heap, id);         // not to my pair!
-          if (L[sgte].prev != NullFreq) // still exists(not removed)
+            decFreq(&S->Heap, id);         // not to my pair!
+          if (S->L[sgte].prev != NullFreq) // still exists(not removed)
           {
-            rec = &Rec.records[id];
-            if (L[sgte].prev < 0) // this cd is head of its list
-              rec->cpos = L[sgte].next;
+            rec = &S->Rec.records[id];
+            if (S->L[sgte].prev < 0) // this cd is head of its list
+              rec->cpos = S->L[sgte].next;
             else
-              L[L[sgte].prev].next = L[sgte].next;
-            if (L[sgte].next != -1) // not tail of its list
-              L[L[sgte].next].prev = L[sgte].prev;
+              S->L[S->L[sgte].prev].next = S->L[sgte].next;
+            if (S->L[sgte].next != -1) // not tail of its list
+              S->L[S->L[sgte].next].prev = S->L[sgte].prev;
           }
         }
         // create occ of ed
-        pair.left = n;
-        id = searchHash(Hash, pair);
+        pair.left = S->n;
+        id = searchHash(S->Hash, pair);
         if (id == -1) // new pair, insert
         {
-          id = insertRecord(&Rec, pair);
-          rec = &Rec.records[id];
-          L[cpos].next = -1;
+          id = insertRecord(&S->Rec, pair);
+          rec = &S->Rec.records[id];
+          S->L[cpos].next = -1;
         } else {
-          incFreq(&Heap, id);
-          rec = &Rec.records[id];
-          L[cpos].next = rec->cpos;
-          L[L[cpos].next].prev = cpos;
+          incFreq(&S->Heap, id);
+          rec = &S->Rec.records[id];
+          S->L[cpos].next = rec->cpos;
+          S->L[S->L[cpos].next].prev = cpos;
         }
-        L[cpos].prev = -id - 1;
+        S->L[cpos].prev = -id - 1;
         rec->cpos = cpos;
       }
       if (cpos != 0) // there is ant
       {              // remove occ of ab
-        if (C[cpos - 1] < 0) {
-          ant = -C[cpos - 1] - 1;
+        if (S->C[cpos - 1] < 0) {
+          ant = -S->C[cpos - 1] - 1;
           if (ant == cpos) // sgte and ant clashed -> 1 hole
             ant = cpos - 2;
         } else
           ant = cpos - 1;
-        pair.left = C[ant];
-        pair.right = C[cpos];
-        id = searchHash(Hash, pair);
+        pair.left = S->C[ant];
+        pair.right = S->C[cpos];
+        id = searchHash(S->Hash, pair);
         if (id != -1) // may not exist if purgeHeap'd
         {
           if (id != oid)
-            decFreq(&Heap, id);        // not to my pair!
-          if (L[ant].prev != NullFreq) // still exists (not removed)
+            decFreq(&S->Heap, id);        // not to my pair!
+          if (S->L[ant].prev != NullFreq) // still exists (not removed)
           {
-            rec = &Rec.records[id];
-            if (L[ant].prev < 0) // this ab is head of its list
-              rec->cpos = L[ant].next;
+            rec = &S->Rec.records[id];
+            if (S->L[ant].prev < 0) // this ab is head of its list
+              rec->cpos = S->L[ant].next;
             else
-              L[L[ant].prev].next = L[ant].next;
-            if (L[ant].next != -1) // it is not tail of its list
-              L[L[ant].next].prev = L[ant].prev;
+              S->L[S->L[ant].prev].next = S->L[ant].next;
+            if (S->L[ant].next != -1) // it is not tail of its list
+              S->L[S->L[ant].next].prev = S->L[ant].prev;
           }
         }
         // create occ of ae
-        pair.right = n;
-        id = searchHash(Hash, pair);
+        pair.right = S->n;
+        id = searchHash(S->Hash, pair);
         if (id == -1) // new pair, insert
         {
-          id = insertRecord(&Rec, pair);
-          rec = &Rec.records[id];
-          L[ant].next = -1;
+          id = insertRecord(&S->Rec, pair);
+          rec = &S->Rec.records[id];
+          S->L[ant].next = -1;
         } else {
-          incFreq(&Heap, id);
-          rec = &Rec.records[id];
-          L[ant].next = rec->cpos;
-          L[L[ant].next].prev = ant;
+          incFreq(&S->Heap, id);
+          rec = &S->Rec.records[id];
+          S->L[ant].next = rec->cpos;
+          S->L[S->L[ant].next].prev = ant;
         }
-        L[ant].prev = -id - 1;
+        S->L[ant].prev = -id - 1;
         rec->cpos = ant;
       }
-      C[cpos] = n;
-      if (ssgte != u)
-        C[ssgte - 1] = -cpos - 1;
-      C[cpos + 1] = -ssgte - 1;
-      c--;
-      orec = &Rec.records[oid]; // just in case of Rec.records realloc'd
+      S->C[cpos] = S->n;
+      if (ssgte != S->u)
+        S->C[ssgte - 1] = -cpos - 1;
+      S->C[cpos + 1] = -ssgte - 1;
+      S->c--;
+      orec = &S->Rec.records[oid]; // just in case of S->Rec.records realloc'd
       cpos = orec->cpos;
     }
     if (PRNC)
-      prnC();
-    removeRecord(&Rec, oid);
-    n++;
-    purgeHeap(&Heap);   // remove freq 1 from heap
-    if (c < factor * u) // compact C
+      prnC(S);
+    removeRecord(&S->Rec, oid);
+    S->n++;
+    purgeHeap(&S->Heap);   // remove freq 1 from heap
+    if (S->c < S->factor * S->u) // compact C
     {
-      int i, ni;
+      Tint i, ni;
       i = 0;
-      for (ni = 0; ni < c - 1; ni++) {
-        C[ni] = C[i];
-        L[ni] = L[i];
-        if (L[ni].prev < 0) {
-          if (L[ni].prev != NullFreq) // real ptr
-            Rec.records[-L[ni].prev - 1].cpos = ni;
+      for (ni = 0; ni < S->c - 1; ni++) {
+        S->C[ni] = S->C[i];
+        S->L[ni] = S->L[i];
+        if (S->L[ni].prev < 0) {
+          if (S->L[ni].prev != NullFreq) // real ptr
+            S->Rec.records[-S->L[ni].prev - 1].cpos = ni;
         } else
-          L[L[ni].prev].next = ni;
-        if (L[ni].next != -1)
-          L[L[ni].next].prev = ni;
+          S->L[S->L[ni].prev].next = ni;
+        if (S->L[ni].next != -1)
+          S->L[S->L[ni].next].prev = ni;
         i++;
-        if (C[i] < 0)
-          i = -C[i] - 1;
+        if (S->C[i] < 0)
+          i = -S->C[i] - 1;
       }
-      C[ni] = C[i];
-      u = c;
-      C = realloc(C, c * sizeof(int));
-      L = realloc(L, c * sizeof(Tlist));
-      assocRecords(&Rec, &Hash, &Heap, L);
+      S->C[ni] = S->C[i];
+      S->u = S->c;
+      S->C = realloc(S->C, S->c * sizeof(Tint));
+      S->L = realloc(S->L, S->c * sizeof(Tlist));
+      assocRecords(&S->Rec, &S->Hash, &S->Heap, S->L);
     }
   }
-  return 0;
 }
 
-void run_repair(int argc, char **argv)
+// releases everything prepare and repair allocated. also safe to call
+// after an allocation failure left the structures partially built
+
+static void release(Trepair *S)
 
 {
-  char fname[1024];
-  char *text;
-  FILE *Tf, *Rf, *Cf;
-  int i, len;
-  struct stat s;
-  if (argc != 2) {
-    fprintf(stderr,
-            "Usage: %s <filename>\n"
-            "Compresses <filename> with repair and creates "
-            "<filename>.ext compressed files\n\n",
-            argv[0]);
-    exit(1);
-  }
-  if (stat(argv[1], &s) != 0) {
-    fprintf(stderr, "Error: cannot stat file %s\n", argv[1]);
-    exit(1);
-  }
-  len = s.st_size;
-  Tf = fopen(argv[1], "r");
-  if (Tf == NULL) {
-    fprintf(stderr, "Error: cannot open file %s for reading\n", argv[1]);
-    exit(1);
-  }
-  text = (void *)malloc(len * sizeof(char));
-  if (fread(text, 1, len, Tf) != len) {
-    fprintf(stderr, "Error: cannot read file %s\n", argv[1]);
-    exit(1);
-  }
-  fclose(Tf);
-  prepare(text, len);
-  strcpy(fname, argv[1]);
-  strcat(fname, ".R");
-  Rf = fopen(fname, "w");
-  if (Rf == NULL) {
-    fprintf(stderr, "Error: cannot open file %s for writing\n", fname);
-    exit(1);
-  }
-  if (repair(Rf) != 0) {
-    fprintf(stderr, "Error: cannot write file %s\n", fname);
-    exit(1);
-  }
-  if (fclose(Rf) != 0) {
-    fprintf(stderr, "Error: cannot close file %s\n", fname);
-    exit(1);
+  destroyHeap(&S->Heap);
+  destroyHash(&S->Hash);
+  destroyRecords(&S->Rec);
+  free(S->C);
+  free(S->L);
+  S->C = NULL;
+  S->L = NULL;
+}
+
+// runs repair on either text or itext, whichever is not NULL
+
+static int run(Trepair *S, const unsigned char *text, const unsigned int *itext,
+               Tint len, Tbuffer *R, Tbuffer *C_out)
+
+{
+  Tint i;
+  jmp_buf env;
+  if (setjmp(env) != 0) {
+    oomHandler = NULL;
+    release(S);
+    return REPAIR_OUT_OF_MEMORY;
   }
-  strcpy(fname, argv[1]);
-  strcat(fname, ".C");
-  Cf = fopen(fname, "w");
-  if (Cf == NULL) {
-    fprintf(stderr, "Error: cannot open file %s for writing\n", fname);
-    exit(1);
+  oomHandler = &env;
+  if (len == 0) { // nothing to compress, but R still needs its header
+    S->alph = 0;
+    writeBuffer(R, &S->alph, sizeof(Tint));
+    oomHandler = NULL;
+    return REPAIR_OK;
   }
+  if (S->ints)
+    loadInts(S, itext, len);
+  else
+    loadChars(S, text, len);
+  prepare(S);
+  repair(S, R);
   i = 0;
-  while (i < u) {
-    if (fwrite(C + i, sizeof(int), 1, Cf) != 1) {
-      fprintf(stderr, "Error: cannot write file %s\n", fname);
-      exit(1);
-    }
+  while (i < S->u) {
+    writeBuffer(C_out, S->C + i, sizeof(Tint));
     i++;
-    if ((i < u) && (C[i] < 0))
-      i = -C[i] - 1;
-  }
-  if (fclose(Cf) != 0) {
-    fprintf(stderr, "Error: cannot close file %s\n", fname);
-    exit(1);
+    if ((i < S->u) && (S->C[i] < 0))
+      i = -S->C[i] - 1;
   }
   if (PRNCf)
-    prnC();
-  //fprintf(stderr, "RePair succeeded\n\n");
-  //fprintf(stderr, "   Original chars: %i\n", len);
-  //fprintf(stderr, "   Number of rules: %i\n", n - alph);
-  //fprintf(stderr, "   Final sequence length: %i\n", c);
-  //fprintf(stderr, "   Compression ratio: %0.2f%%\n",
-  //        (2.0 * (n - alph) + c) * (float)blog(n - 1) / (len * 8.0) * 100.0);
-  //exit(0);
+    prnC(S);
+  oomHandler = NULL;
+  release(S);
+  return REPAIR_OK;
+}
+
+static void init(Trepair *S, float factor, Tint minsize, int ints)
+
+{
+  S->factor = factor;
+  S->minsize = minsize;
+  S->ints = ints;
+  S->C = NULL;
+  S->L = NULL;
+  memset(&S->Heap, 0, sizeof(Theap));
+  memset(&S->Hash, 0, sizeof(Thash));
+  S->Rec = createRecords(factor, minsize);
+}
+
+// runs repair on text[0..len-1], appending the contents of the .R file
+// to R and the contents of the .C file to C. unlike the original files,
+// all integers in them are 64 bits wide. text is not modified and
+// remains owned by the caller, who must release R and C with freeBuffer.
+// 0 < factor < 1 and minsize > 0 trade space for time as described in Trepair.
+// returns REPAIR_OK, or REPAIR_OUT_OF_MEMORY if an allocation failed.
+// all state lives in this call, so concurrent calls do not interfere
+
+int repair_buffer(const unsigned char *text, Tint len, float factor,
+                  Tint minsize, Tbuffer *R, Tbuffer *C_out)
+
+{
+  Trepair S; // kept out of run, whose locals are unreliable after longjmp
+  init(&S, factor, minsize, 0);
+  return run(&S, text, NULL, len, R, C_out);
+}
+
+// same as repair_buffer, but for a sequence of ints as irepair.
+// the .R output has no alphabet map then, as the terminals are the ints
+// themselves
+
+int repair_int_buffer(const unsigned int *text, Tint len, float factor,
+                      Tint minsize, Tbuffer *R, Tbuffer *C_out)
+
+{
+  Trepair S;
+  init(&S, factor, minsize, 1);
+  return run(&S, NULL, text, len, R, C_out);
 }
@@ -0,0 +1,205 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+use crate::{error::{RePairError, RReaderError}, grammar::Grammar, native_repair};
+
+#[cfg(feature = "navarro")]
+use crate::coding::{
+    grammar_coder::GrammarDecoder,
+    navarro_repair_decoder::{NavarroIntRepairDecoder, NavarroRepairDecoder},
+};
+
+/// Space/time trade-offs of RePair
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RePairOptions {
+    /// The sequence is compacted once it shrinks below this fraction of its allocated size.
+    /// Arrays and tables grow by `1 / factor` when full. Must be in `(0, 1)`.
+    /// Closer to 1 uses less memory, but is slower.
+    pub factor: f32,
+    /// The minimum size of each of the C implementation's growable arrays, to avoid many reallocations at small sizes.
+    /// Must be positive. The native backend ignores it.
+    pub minsize: usize,
+}
+
+impl Default for RePairOptions {
+    fn default() -> Self {
+        RePairOptions {
+            factor: 0.5,
+            minsize: 256,
+        }
+    }
+}
+
+impl RePairOptions {
+    pub fn validate(&self) -> Result<(), RePairError> {
+        if !(self.factor > 0.0 && self.factor < 1.0) {
+            return Err(RePairError::InvalidOptions("factor must be between 0 and 1"));
+        }
+        // The C implementation takes minsize as its 64-bit Tint
+        if self.minsize == 0 || std::os::raw::c_longlong::try_from(self.minsize).is_err() {
+            return Err(RePairError::InvalidOptions("minsize must be positive and fit into a C long long"));
+        }
+        Ok(())
+    }
+}
+
+/// The RePair implementation used to compute a grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Gonzalo Navarro's C implementation. Only available with the `navarro` feature.
+    #[cfg(feature = "navarro")]
+    #[default]
+    Navarro,
+    /// The pure Rust implementation in [`native_repair`]
+    #[cfg_attr(not(feature = "navarro"), default)]
+    Native,
+}
+
+impl Backend {
+    /// Runs RePair with this backend on an in-memory buffer
+    pub fn repair_bytes(self, text: &[u8]) -> Result<Grammar, RReaderError> {
+        self.repair_with_options(text, &RePairOptions::default())
+    }
+
+    /// Runs RePair with this backend and the given space/time trade-offs on an in-memory buffer
+    pub fn repair_with_options(self, text: &[u8], options: &RePairOptions) -> Result<Grammar, RReaderError> {
+        match self {
+            #[cfg(feature = "navarro")]
+            Backend::Navarro => {
+                NavarroRepairDecoder::decode(crate::bindings::repair_with_options(text, options)?)
+            }
+            Backend::Native => Ok(native_repair::repair_with_options(text, options)?),
+        }
+    }
+
+    /// Runs RePair with this backend on the contents of a file
+    pub fn repair_file(self, file: impl AsRef<str>, options: &RePairOptions) -> Result<Grammar, RReaderError> {
+        self.repair_with_options(&read_input(file.as_ref())?, options)
+    }
+
+    /// Runs RePair with this backend on a sequence of integers.
+    /// The terminals of the grammar are the integers themselves.
+    pub fn repair_ints(self, symbols: &[u32]) -> Result<Grammar, RReaderError> {
+        self.repair_ints_with_options(symbols, &RePairOptions::default())
+    }
+
+    /// Runs RePair with this backend and the given space/time trade-offs on a sequence of integers
+    pub fn repair_ints_with_options(self, symbols: &[u32], options: &RePairOptions) -> Result<Grammar, RReaderError> {
+        match self {
+            #[cfg(feature = "navarro")]
+            Backend::Navarro => {
+                NavarroIntRepairDecoder::decode(crate::bindings::repair_ints_with_options(symbols, options)?)
+            }
+            Backend::Native => Ok(native_repair::repair_ints_with_options(symbols, options)?),
+        }
+    }
+
+    /// Runs RePair with this backend on a file of 32-bit little-endian integers
+    pub fn repair_int_file(self, file: impl AsRef<str>, options: &RePairOptions) -> Result<Grammar, RReaderError> {
+        let bytes = read_input(file.as_ref())?;
+        if bytes.len() % 4 != 0 {
+            return Err(RReaderError::MissingInput("Bytes of the last integer"));
+        }
+        let symbols = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect::<Vec<_>>();
+        self.repair_ints_with_options(&symbols, options)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "navarro")]
+            "navarro" => Ok(Backend::Navarro),
+            "native" => Ok(Backend::Native),
+            _ => Err(format!("unknown RePair backend {s}")),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "navarro")]
+            Backend::Navarro => write!(f, "navarro"),
+            Backend::Native => write!(f, "native"),
+        }
+    }
+}
+
+/// Reads a file to be compressed, reporting missing and unreadable files separately
+pub(crate) fn read_input(file: &str) -> Result<Vec<u8>, RePairError> {
+    let path = Path::new(file);
+    std::fs::metadata(path).map_err(|err| RePairError::CannotStat(file.to_owned(), err))?;
+    std::fs::read(path).map_err(|err| RePairError::CannotRead(file.to_owned(), err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backend, RePairOptions};
+
+    #[test]
+    fn backend_parse_test() {
+        assert_eq!(Ok(Backend::Native), "native".parse(), "Native backend not parsed");
+        #[cfg(feature = "navarro")]
+        assert_eq!(Ok(Backend::Navarro), "navarro".parse(), "Navarro backend not parsed");
+        assert!("gzip".parse::<Backend>().is_err(), "Unknown backend parsed");
+    }
+
+    #[test]
+    fn backend_repair_test() {
+        let text = b"how much wood would a woodchuck chuck if a woodchuck could chuck wood";
+        let gr = Backend::default().repair_bytes(text);
+        assert!(gr.is_ok(), "Error during RePair: {:?}", gr.err());
+        assert_eq!(
+            Ok(String::from_utf8(text.to_vec()).unwrap()),
+            gr.unwrap().produce_source_string(),
+            "Grammar producing the wrong string"
+        );
+    }
+
+    #[test]
+    fn options_validate_test() {
+        assert!(RePairOptions::default().validate().is_ok(), "Default options invalid");
+        for factor in [0.0, 1.0, -0.5, 2.0, f32::NAN] {
+            let options = RePairOptions { factor, ..Default::default() };
+            assert!(options.validate().is_err(), "Factor {factor} accepted");
+        }
+        let options = RePairOptions { minsize: 0, ..Default::default() };
+        assert!(options.validate().is_err(), "Minsize 0 accepted");
+        #[cfg(target_pointer_width = "64")]
+        {
+            let options = RePairOptions { minsize: 1 << 40, ..Default::default() };
+            assert!(options.validate().is_ok(), "Minsize over 32 bits rejected");
+            let options = RePairOptions { minsize: usize::MAX, ..Default::default() };
+            assert!(options.validate().is_err(), "Minsize over 63 bits accepted");
+        }
+    }
+
+    #[test]
+    fn backend_options_test() {
+        let text = (0..20000usize).map(|i| b'a' + (i % 13 + i % 7) as u8).collect::<Vec<_>>();
+        let options = RePairOptions { factor: 0.9, minsize: 16 };
+        let gr = Backend::default().repair_with_options(&text, &options);
+        assert!(gr.is_ok(), "Error during RePair: {:?}", gr.err());
+
+        let mut out = vec![];
+        gr.unwrap().write_source_string(&mut out).unwrap();
+        assert_eq!(text, out, "Grammar producing the wrong text");
+    }
+
+    #[test]
+    fn backend_ints_test() {
+        let symbols = (0..5000u32).map(|i| (i * 7 % 11) * 1000 + i % 3).collect::<Vec<_>>();
+        let gr = Backend::default().repair_ints(&symbols);
+        assert!(gr.is_ok(), "Error during RePair: {:?}", gr.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            gr.unwrap().produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+    }
+}
@@ -1,37 +1,274 @@
-/* automatically generated by rust-bindgen 0.59.2 */
+//! Bindings to Navarro's RePair in `external/repair`, which is compiled when the `navarro` feature is enabled.
+//! The declarations follow the C sources and are maintained by hand.
 
-use std::{ffi::CString, path::Path};
+use crate::{
+    backend::{read_input, RePairOptions},
+    coding::navarro_repair_decoder::{IntWidth, RePairResult},
+    error::RePairError,
+};
 
-use crate::{error::RePairError, coding::navarro_repair_decoder::RePairResult};
+#[repr(C)]
+struct Tbuffer {
+    data: *mut ::std::os::raw::c_char,
+    size: usize,
+    capacity: usize,
+}
 
-const TWO: ::std::os::raw::c_int = 2;
+const REPAIR_OK: ::std::os::raw::c_int = 0;
+const REPAIR_OUT_OF_MEMORY: ::std::os::raw::c_int = 1;
 
 extern "C" {
-    fn run_repair(argc: ::std::os::raw::c_int, argv: *const *const ::std::os::raw::c_char);
+    fn repair_buffer(
+        text: *const ::std::os::raw::c_uchar,
+        len: ::std::os::raw::c_longlong,
+        factor: ::std::os::raw::c_float,
+        minsize: ::std::os::raw::c_longlong,
+        R: *mut Tbuffer,
+        C: *mut Tbuffer,
+    ) -> ::std::os::raw::c_int;
+    fn repair_int_buffer(
+        text: *const ::std::os::raw::c_uint,
+        len: ::std::os::raw::c_longlong,
+        factor: ::std::os::raw::c_float,
+        minsize: ::std::os::raw::c_longlong,
+        R: *mut Tbuffer,
+        C: *mut Tbuffer,
+    ) -> ::std::os::raw::c_int;
+    fn freeBuffer(B: *mut Tbuffer);
 }
 
+impl Tbuffer {
+    fn new() -> Self {
+        Tbuffer {
+            data: std::ptr::null_mut(),
+            size: 0,
+            capacity: 0,
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        if self.data.is_null() {
+            return vec![];
+        }
+        // SAFETY: The C side guarantees that data points to at least size initialized bytes
+        unsafe { std::slice::from_raw_parts(self.data as *const u8, self.size) }.to_vec()
+    }
+}
+
+impl Drop for Tbuffer {
+    fn drop(&mut self) {
+        unsafe { freeBuffer(self) }
+    }
+}
 
 pub fn repair(file: impl AsRef<str>) -> Result<RePairResult, RePairError> {
-    let file = file.as_ref();
-    // Try to open the file. We do this to quit with an error if the file doesn't exist
-    std::fs::File::open(Path::new(file))?;
+    repair_bytes(&read_input(file.as_ref())?)
+}
+
+/// Runs RePair on an in-memory buffer.
+/// The contents of the `.R` and `.C` files are collected directly, so nothing is written to disk.
+/// Each call has its own RePair state, so this can be called from multiple threads at once.
+pub fn repair_bytes(text: &[u8]) -> Result<RePairResult, RePairError> {
+    repair_with_options(text, &RePairOptions::default())
+}
+
+/// Runs RePair on an in-memory buffer with the given space/time trade-offs.
+pub fn repair_with_options(text: &[u8], options: &RePairOptions) -> Result<RePairResult, RePairError> {
+    options.validate()?;
+
+    // The C implementation indexes the text with 64-bit ints
+    let len = ::std::os::raw::c_longlong::try_from(text.len())
+        .map_err(|_| RePairError::InputTooLarge(text.len()))?;
+
+    let mut file_r = Tbuffer::new();
+    let mut file_c = Tbuffer::new();
+
+    let status = unsafe {
+        repair_buffer(
+            text.as_ptr(),
+            len,
+            options.factor,
+            options.minsize as ::std::os::raw::c_longlong,
+            &mut file_r,
+            &mut file_c,
+        )
+    };
+
+    collect(status, file_r, file_c)
+}
+
+/// Runs RePair on a sequence of integers, like Navarro's `irepair`.
+/// The result has no alphabet map and has to be decoded with
+/// [`NavarroIntRepairDecoder`](crate::coding::navarro_repair_decoder::NavarroIntRepairDecoder).
+pub fn repair_ints(symbols: &[u32]) -> Result<RePairResult, RePairError> {
+    repair_ints_with_options(symbols, &RePairOptions::default())
+}
+
+/// Runs RePair on a sequence of integers with the given space/time trade-offs.
+pub fn repair_ints_with_options(symbols: &[u32], options: &RePairOptions) -> Result<RePairResult, RePairError> {
+    options.validate()?;
+
+    // The rules are numbered after the terminals, which always fit next to 32-bit symbols
+    let len = ::std::os::raw::c_longlong::try_from(symbols.len())
+        .map_err(|_| RePairError::InputTooLarge(symbols.len()))?;
+
+    let mut file_r = Tbuffer::new();
+    let mut file_c = Tbuffer::new();
+
+    let status = unsafe {
+        repair_int_buffer(
+            symbols.as_ptr(),
+            len,
+            options.factor,
+            options.minsize as ::std::os::raw::c_longlong,
+            &mut file_r,
+            &mut file_c,
+        )
+    };
+
+    collect(status, file_r, file_c)
+}
+
+fn collect(status: ::std::os::raw::c_int, file_r: Tbuffer, file_c: Tbuffer) -> Result<RePairResult, RePairError> {
+    match status {
+        REPAIR_OK => Ok(RePairResult {
+            file_c: file_c.to_vec(),
+            file_r: file_r.to_vec(),
+            int_width: IntWidth::U64,
+        }),
+        REPAIR_OUT_OF_MEMORY => Err(RePairError::OutOfMemory),
+        code => Err(RePairError::UnknownStatus(code)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coding::{
+            grammar_coder::GrammarDecoder,
+            navarro_repair_decoder::{NavarroIntRepairDecoder, NavarroRepairDecoder},
+        },
+        error::RePairError,
+    };
+
+    use super::{collect, repair, repair_bytes, repair_ints, Tbuffer};
+
+    #[test]
+    fn repair_bytes_test() {
+        let text = b"abracadabra abracadabra abracadabra";
+        let res = repair_bytes(text);
+        assert!(res.is_ok(), "Error during RePair: {:?}", res.err());
+
+        let gr = NavarroRepairDecoder::decode(res.unwrap());
+        assert!(gr.is_ok(), "Error decoding grammar: {:?}", gr.err());
+
+        let s = gr.unwrap().produce_source_string();
+        assert_eq!(
+            Ok(String::from_utf8(text.to_vec()).unwrap()),
+            s,
+            "Grammar producing the wrong string"
+        );
+    }
+
+    #[test]
+    fn repair_empty_test() {
+        let res = repair_bytes(&[]);
+        assert!(res.is_ok(), "Error during RePair: {:?}", res.err());
+
+        let gr = NavarroRepairDecoder::decode(res.unwrap());
+        assert!(gr.is_ok(), "Error decoding grammar: {:?}", gr.err());
+
+        assert_eq!(
+            Ok(String::new()),
+            gr.unwrap().produce_source_string(),
+            "Empty input producing a non-empty string"
+        );
+    }
+
+    #[test]
+    fn repair_missing_file_test() {
+        let res = repair("this/file/does/not/exist.txt");
+        assert!(
+            matches!(res, Err(RePairError::CannotStat(_, _))),
+            "Missing file not reported as unstattable"
+        );
+    }
+
+    #[test]
+    fn unknown_status_test() {
+        assert!(
+            matches!(collect(42, Tbuffer::new(), Tbuffer::new()), Err(RePairError::UnknownStatus(42))),
+            "Unknown status code not reported as an error"
+        );
+    }
+
+    #[test]
+    fn repair_concurrent_test() {
+        // Texts of differing size and alphabet, so crosstalk between the runs would show
+        let texts = (0..8u8)
+            .map(|i| {
+                (0..2000 + 500 * i as usize)
+                    .map(|j| b'a' + ((j * (i as usize + 1)) % (3 + i as usize)) as u8)
+                    .collect::<Vec<u8>>()
+            })
+            .collect::<Vec<_>>();
+
+        let handles = texts
+            .iter()
+            .cloned()
+            .map(|text| {
+                std::thread::spawn(move || {
+                    let res = repair_bytes(&text).expect("Error during RePair");
+                    NavarroRepairDecoder::decode(res)
+                        .expect("Error decoding grammar")
+                        .produce_source_string()
+                        .expect("Grammar producing invalid UTF-8")
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (text, handle) in texts.into_iter().zip(handles) {
+            assert_eq!(
+                String::from_utf8(text).unwrap(),
+                handle.join().expect("RePair thread panicked"),
+                "Concurrent RePair producing the wrong string"
+            );
+        }
+    }
 
-    let file_c_str = CString::new(file.trim())?;
-    // The binary path is unneeded for repair, so we can just pass an empty string.
-    let binary_path = CString::new("")?;
+    #[test]
+    fn repair_ints_test() {
+        let symbols = (0..3000u32).map(|i| (i % 17) * 100_000 + i % 5).collect::<Vec<_>>();
+        let res = repair_ints(&symbols);
+        assert!(res.is_ok(), "Error during RePair: {:?}", res.err());
 
-    let argv = [binary_path.as_ptr(), file_c_str.as_ptr()];
+        let gr = NavarroIntRepairDecoder::decode(res.unwrap());
+        assert!(gr.is_ok(), "Error decoding grammar: {:?}", gr.err());
+        let gr = gr.unwrap();
 
-    unsafe { run_repair(TWO, argv.as_ptr()) }
+        assert_eq!(1_600_005, gr.alphabet_size(), "Alphabet size is not the largest symbol + 1");
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            gr.produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+    }
 
-    let file_c_name = format!("{file}.C");
-    let file_r_name = format!("{file}.R");
-    
-    let file_c = std::fs::read(&file_c_name)?;
-    let file_r = std::fs::read(&file_r_name)?;
+    #[test]
+    fn repair_ints_large_symbols_test() {
+        let symbols = [u32::MAX, 0, u32::MAX, 0, 1];
+        let res = repair_ints(&symbols);
+        assert!(res.is_ok(), "Error during RePair: {:?}", res.err());
 
-    std::fs::remove_file(file_c_name)?;
-    std::fs::remove_file(file_r_name)?;
+        let gr = NavarroIntRepairDecoder::decode(res.unwrap());
+        assert!(gr.is_ok(), "Error decoding grammar: {:?}", gr.err());
+        let gr = gr.unwrap();
 
-    Ok(RePairResult { file_c, file_r })
+        assert_eq!(1 << 32, gr.alphabet_size(), "Alphabet size is not the largest symbol + 1");
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            gr.produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+    }
 }
@@ -0,0 +1,457 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{Error, ErrorKind, Read, Write},
+};
+
+use bitstream_io::{BigEndian, BitRead, BitReader, BitWrite, BitWriter};
+
+use crate::{error::RReaderError, grammar::Grammar};
+
+use super::{
+    grammar_coder::{read_alphabet_size, read_magic, write_alphabet_size, write_magic, GrammarDecoder, GrammarEncoder},
+    grammar_huffman_coder::{read_symbol_table, write_symbol_table},
+    grammar_tuple_coder::{to_u32, GrammarTupleCoder, MAX_PREALLOCATION},
+    number_coder::{invalid, Gamma, NumberDecoder, NumberEncoder},
+};
+
+/// The lower bound of the coder's state. Whole 32-bit words are moved in and out of the state to keep it above.
+const STATE_LOW: u64 = 1 << 31;
+/// The fewest bits the frequencies are scaled to a total of, to keep the rounding error small
+const MIN_PROB_BITS: u32 = 16;
+/// The most bits the frequencies are scaled to a total of. The state's lower bound must be at least the total.
+const MAX_PROB_BITS: u32 = 31;
+
+/// Writes the symbols of the renumbered grammar with range asymmetric numeral systems (rANS),
+/// using a static model of the symbols' frequencies in the rules. This comes within a fraction of a bit of
+/// their entropy, unlike Huffman codes, which need a whole number of bits per symbol.
+///
+/// The file starts with the magic bytes `RRGA` and the format version,
+/// followed by the rule count and the minimum rule length as 32-bit numbers and the alphabet size as a 64-bit number.
+/// How often each symbol occurs follows like the code lengths of [`super::grammar_huffman_coder::GrammarHuffmanCoder`],
+/// and then the length of each rule minus the minimum in gamma code. The decoder scales the counts to frequencies like the encoder,
+/// where no symbol gets more than half of the total.
+/// After aligning to a byte, the coder's final 64-bit state and the 32-bit words it emitted follow in reverse order.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GrammarAnsCoder;
+
+const MAGIC: &[u8; 4] = b"RRGA";
+const VERSION: u32 = 2;
+
+impl GrammarEncoder for GrammarAnsCoder {
+    type EncodeErr = std::io::Error;
+
+    fn encode<Out: std::io::Write>(mut grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
+        let mut bit_writer = BitWriter::endian(out, BigEndian);
+        grammar.prune();
+        grammar.renumber();
+
+        let symbol_count = grammar.alphabet_size() as u64 + grammar.rule_count() as u64;
+        let mut counts = BTreeMap::new();
+        for &symbol in grammar.iter().flatten() {
+            *counts.entry(symbol).or_insert(0u64) += 1;
+        }
+        let counts = counts.into_iter().collect::<Vec<_>>();
+        let prob_bits = prob_bits(counts.len()).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} distinct symbols are too many for the model", counts.len()),
+            )
+        })?;
+        let model = Model::new(&counts, prob_bits);
+
+        let min_len = grammar.iter().map(Vec::len).min().unwrap_or(0);
+        write_magic(&mut bit_writer, MAGIC, VERSION)?;
+        for (value, what) in [(grammar.rule_count(), "rule count"), (min_len, "rule length")] {
+            bit_writer.write_bytes(&to_u32(value, what)?.to_be_bytes())?;
+        }
+        write_alphabet_size(&mut bit_writer, grammar.alphabet_size())?;
+        write_symbol_table(&mut bit_writer, symbol_count, counts.iter().copied())?;
+        for rule in grammar.iter() {
+            Gamma::encode_number((rule.len() - min_len) as u64, &mut bit_writer)?;
+        }
+        bit_writer.byte_align()?;
+
+        // rANS decodes in the opposite order of encoding, so the symbols are encoded backwards
+        let mut state = STATE_LOW;
+        let mut words = vec![];
+        for &symbol in grammar.iter().flatten().rev() {
+            let (start, frequency) = model.interval(symbol);
+            let state_max = ((STATE_LOW >> prob_bits) << 32) * frequency;
+            while state >= state_max {
+                words.push(state as u32);
+                state >>= 32;
+            }
+            state = ((state / frequency) << prob_bits) + state % frequency + start;
+        }
+        bit_writer.write(64, state)?;
+        for word in words.into_iter().rev() {
+            bit_writer.write(32, word)?;
+        }
+        bit_writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<I> GrammarDecoder<I> for GrammarAnsCoder
+where
+    I: Read,
+{
+    type DecodeErr = RReaderError;
+
+    fn decode(input: I) -> Result<Grammar, Self::DecodeErr> {
+        let mut bit_reader = BitReader::endian(input, BigEndian);
+        read_magic(&mut bit_reader, MAGIC, VERSION, "ans")?;
+        let mut header = [0usize; 2];
+        for field in header.iter_mut() {
+            let mut buf32 = [0u8; 4];
+            bit_reader.read_bytes(&mut buf32)?;
+            *field = u32::from_be_bytes(buf32) as usize;
+        }
+        let [rule_count, min_len] = header;
+        let alphabet_size = read_alphabet_size(&mut bit_reader)?;
+
+        let symbol_count = alphabet_size.checked_add(rule_count).ok_or(RReaderError::TooLarge("alphabet size"))? as u64;
+        let counts = read_symbol_table(&mut bit_reader, symbol_count, u64::MAX)?;
+        let prob_bits = prob_bits(counts.len()).ok_or_else(|| invalid("too many distinct symbols"))?;
+        let model = Model::new(&counts, prob_bits);
+
+        let mut rule_lens = Vec::with_capacity(rule_count.min(MAX_PREALLOCATION));
+        for _ in 0..rule_count {
+            let rule_len = usize::try_from(Gamma::decode_number(&mut bit_reader)?)
+                .ok()
+                .and_then(|len| len.checked_add(min_len))
+                .ok_or(RReaderError::TooLarge("rule length"))?;
+            rule_lens.push(rule_len);
+        }
+        let count_sum = counts.iter().try_fold(0u64, |sum, &(_, count)| sum.checked_add(count));
+        let len_sum = rule_lens.iter().try_fold(0u64, |sum, &len| sum.checked_add(len as u64));
+        if count_sum.is_none() || count_sum != len_sum {
+            return Err(invalid("rule lengths do not add up to the symbol counts").into());
+        }
+        bit_reader.byte_align();
+
+        let mut state = bit_reader.read::<u64>(64)?;
+        if !(STATE_LOW..STATE_LOW << 32).contains(&state) {
+            return Err(invalid("coder state out of range").into());
+        }
+        let mut rules = Vec::with_capacity(rule_lens.len());
+        for rule_len in rule_lens {
+            let mut rule = Vec::with_capacity(rule_len.min(MAX_PREALLOCATION));
+            for _ in 0..rule_len {
+                let slot = state & ((1 << prob_bits) - 1);
+                let (symbol, start, frequency) = model.find(slot).ok_or_else(|| invalid("no symbol to decode"))?;
+                state = frequency * (state >> prob_bits) + slot - start;
+                while state < STATE_LOW {
+                    state = (state << 32) | bit_reader.read::<u64>(32)?;
+                }
+                rule.push(symbol);
+            }
+            rules.push(rule);
+        }
+        // The encoder started from the lower bound, so ending anywhere else means the data is corrupt
+        if state != STATE_LOW {
+            return Err(invalid("coder did not end in its initial state").into());
+        }
+
+        let grammar = Grammar::from_parts_with_alphabet(rules, rule_count.saturating_sub(1), alphabet_size);
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
+
+/// The encoded sizes of a grammar with [`GrammarAnsCoder`] and [`GrammarTupleCoder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeComparison {
+    pub ans_bytes: usize,
+    pub tuple_bytes: usize,
+}
+
+impl Display for SizeComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ans: {} bytes, tuple: {} bytes", self.ans_bytes, self.tuple_bytes)?;
+        if self.tuple_bytes > 0 {
+            write!(f, " ({:.1}%)", 100.0 * self.ans_bytes as f64 / self.tuple_bytes as f64)?;
+        }
+        Ok(())
+    }
+}
+
+impl GrammarAnsCoder {
+    /// Encodes the grammar with this coder and [`GrammarTupleCoder`], to see which one is worth using
+    pub fn compare_with_tuple(grammar: &Grammar) -> std::io::Result<SizeComparison> {
+        let mut ans = ByteCounter(0);
+        GrammarAnsCoder::encode(grammar.clone(), &mut ans)?;
+        let mut tuple = ByteCounter(0);
+        GrammarTupleCoder::encode(grammar.clone(), &mut tuple)?;
+        Ok(SizeComparison {
+            ans_bytes: ans.0,
+            tuple_bytes: tuple.0,
+        })
+    }
+}
+
+/// Discards what is written, only counting the bytes
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The bits of the total the frequencies of this many symbols are scaled to, if there are not too many symbols
+fn prob_bits(symbols: usize) -> Option<u32> {
+    // Leave at least 8 bits of precision for each symbol on average
+    let prob_bits = (usize::BITS - symbols.leading_zeros() + 8).max(MIN_PROB_BITS);
+    (prob_bits <= MAX_PROB_BITS).then_some(prob_bits)
+}
+
+/// Scales the counts to frequencies adding up to `2^prob_bits`, where each count keeps a frequency of at least 1.
+/// No frequency exceeds half the total, so every symbol takes at least one bit of the coder's state,
+/// and the slots a capped symbol would have taken decode to no symbol at all.
+/// There must be at most `2^(prob_bits - 1)` counts.
+fn scale_frequencies(counts: &[u64], prob_bits: u32) -> Vec<u64> {
+    let total = 1u64 << prob_bits;
+    let count_sum = counts.iter().map(|&count| count as u128).sum::<u128>().max(1);
+    let mut frequencies = counts
+        .iter()
+        .map(|&count| ((count as u128 * total as u128 / count_sum) as u64).max(1))
+        .collect::<Vec<_>>();
+
+    // Rounding down loses some of the total, while rounding up to 1 can exceed it. The most frequent symbols make up for it.
+    let mut by_frequency = (0..frequencies.len()).collect::<Vec<_>>();
+    // The decoder has to arrive at the same frequencies, so ties are broken by position
+    by_frequency.sort_by_key(|&i| (std::cmp::Reverse(frequencies[i]), i));
+    let sum = frequencies.iter().sum::<u64>();
+    if let Some(&most_frequent) = by_frequency.first() {
+        if sum < total {
+            frequencies[most_frequent] += total - sum;
+        } else {
+            let mut excess = sum - total;
+            for i in by_frequency {
+                let taken = excess.min(frequencies[i] - 1);
+                frequencies[i] -= taken;
+                excess -= taken;
+            }
+        }
+    }
+    // A symbol of probability 1 would cost nothing, so a corrupt file could claim any number of them
+    for frequency in frequencies.iter_mut() {
+        *frequency = (*frequency).min(total / 2);
+    }
+    frequencies
+}
+
+/// The interval of the total each symbol takes up, in the order of the symbols
+struct Model {
+    symbols: Vec<usize>,
+    /// The start of each symbol's interval, followed by the total
+    starts: Vec<u64>,
+}
+
+impl Model {
+    /// Scales how often each symbol occurs to frequencies adding up to at most `2^prob_bits`
+    fn new(counts: &[(usize, u64)], prob_bits: u32) -> Self {
+        let frequencies = scale_frequencies(&counts.iter().map(|&(_, count)| count).collect::<Vec<_>>(), prob_bits);
+        let mut starts = Vec::with_capacity(frequencies.len() + 1);
+        let mut start = 0u64;
+        starts.push(start);
+        for frequency in frequencies {
+            start += frequency;
+            starts.push(start);
+        }
+        let symbols = counts.iter().map(|&(symbol, _)| symbol).collect();
+        Self { symbols, starts }
+    }
+
+    /// The start and frequency of a symbol which occurs in the grammar
+    fn interval(&self, symbol: usize) -> (u64, u64) {
+        let i = self.symbols.binary_search(&symbol).expect("Symbol without frequency encoded");
+        (self.starts[i], self.starts[i + 1] - self.starts[i])
+    }
+
+    /// The symbol whose interval contains the slot, with the interval's start and frequency
+    fn find(&self, slot: u64) -> Option<(usize, u64, u64)> {
+        let i = self.starts.partition_point(|&start| start <= slot).checked_sub(1)?;
+        let symbol = *self.symbols.get(i)?;
+        Some((symbol, self.starts[i], self.starts[i + 1] - self.starts[i]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coding::{
+            grammar_coder::GrammarDecoder,
+            grammar_huffman_coder::{write_symbol_table, GrammarHuffmanCoder},
+            number_coder::{Gamma, NumberEncoder},
+            test_util::encode,
+        },
+        error::RReaderError,
+        grammar::Grammar,
+        native_repair,
+    };
+    use bitstream_io::{BigEndian, BitWrite, BitWriter};
+
+    use super::{scale_frequencies, GrammarAnsCoder, SizeComparison, MAGIC, STATE_LOW, VERSION};
+
+    #[test]
+    fn coding_decoding_test() {
+        let mut gr = Grammar::from_parts(
+            vec![
+                vec![257, 258, 100],
+                vec![97, 98, 99],
+                vec![100, 101, 259],
+                vec![102, 103, 104, 257],
+            ],
+            0,
+        );
+        let decoded = GrammarAnsCoder::decode(encode::<GrammarAnsCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+
+        // The read grammar will be renumbered as it is required by the coder
+        gr.renumber();
+        assert_eq!(gr, decoded.unwrap(), "Resulting grammar differs from original grammar");
+    }
+
+    #[test]
+    fn few_symbols_test() {
+        for gr in [
+            Grammar::empty(),
+            Grammar::from_parts(vec![vec![]], 0),
+            Grammar::from_parts(vec![vec![97]], 0),
+            Grammar::from_parts(vec![vec![97; 1000]], 0),
+            Grammar::from_parts_with_alphabet(vec![vec![4_000_000_000, 7, 7]], 0, 4_000_000_001),
+        ] {
+            let decoded = GrammarAnsCoder::decode(encode::<GrammarAnsCoder>(&gr).as_slice());
+            assert_eq!(Some(gr), decoded.ok(), "Grammar with few symbols not decoded correctly");
+        }
+    }
+
+    #[test]
+    fn repair_test() {
+        let text = (0..50000usize)
+            .map(|i| b'a' + (i % 13 + i % 7 + i / 1000 % 3 + i * i % 5) as u8)
+            .collect::<Vec<_>>();
+        let gr = native_repair::repair_bytes(&text);
+        let ans = encode::<GrammarAnsCoder>(&gr);
+        let decoded = GrammarAnsCoder::decode(ans.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(text, decoded.unwrap().bytes().collect::<Vec<_>>(), "ANS-coded grammar producing the wrong text");
+
+        let comparison = GrammarAnsCoder::compare_with_tuple(&gr).unwrap();
+        assert_eq!(ans.len(), comparison.ans_bytes, "Compared size differs from the encoding");
+        assert!(comparison.ans_bytes < comparison.tuple_bytes, "ANS encoding not smaller: {comparison}");
+        let huffman = encode::<GrammarHuffmanCoder>(&gr);
+        assert!(
+            ans.len() < huffman.len(),
+            "ANS encoding not smaller than Huffman encoding: {} >= {}",
+            ans.len(),
+            huffman.len()
+        );
+    }
+
+    #[test]
+    fn size_comparison_display_test() {
+        let comparison = SizeComparison {
+            ans_bytes: 50,
+            tuple_bytes: 200,
+        };
+        assert_eq!("ans: 50 bytes, tuple: 200 bytes (25.0%)", comparison.to_string(), "Comparison displayed incorrectly");
+    }
+
+    #[test]
+    fn scale_frequencies_test() {
+        for counts in [vec![1; 1 << 15], vec![2, 1, 1], vec![5, 4, 3, 2, 1]] {
+            let frequencies = scale_frequencies(&counts, 16);
+            assert_eq!(1 << 16, frequencies.iter().sum::<u64>(), "Frequencies not adding up to the total");
+        }
+        for counts in [vec![1], vec![3, 1], vec![1_000_000, 1, 1, 1], vec![1; 1 << 15], vec![u64::MAX / 2, 5]] {
+            let frequencies = scale_frequencies(&counts, 16);
+            assert!(frequencies.iter().all(|&frequency| frequency >= 1), "Symbol without frequency");
+            assert!(frequencies.iter().all(|&frequency| frequency <= 1 << 15), "Symbol with more than half the total");
+        }
+        assert_eq!(vec![1 << 15, 1 << 14, 1 << 14], scale_frequencies(&[2, 1, 1], 16), "Frequencies not proportional");
+        assert_eq!(vec![1 << 15], scale_frequencies(&[1], 16), "Single symbol not capped at half the total");
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let gr = Grammar::from_parts(vec![vec![97, 98, 99, 97, 98, 99, 100]], 0);
+        let buf = encode::<GrammarAnsCoder>(&gr);
+        assert!(
+            matches!(GrammarAnsCoder::decode(&buf[..buf.len() - 1]), Err(RReaderError::IO(_))),
+            "Truncated file decoded"
+        );
+
+        // The final state is the last 8 bytes, as the few symbols never made the coder emit a word
+        let mut corrupt = buf;
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        assert!(
+            matches!(GrammarAnsCoder::decode(corrupt.as_slice()), Err(RReaderError::IO(_))),
+            "Corrupt state decoded"
+        );
+    }
+
+    #[test]
+    fn header_without_data_test() {
+        // A single symbol claimed to make up a rule of billions of symbols, with nothing behind the initial state
+        let header = |count: u64, rule_len: u64| {
+            let mut buf = vec![];
+            let mut writer = BitWriter::endian(&mut buf, BigEndian);
+            writer.write_bytes(MAGIC).unwrap();
+            for field in [VERSION, 1, 0] {
+                writer.write(32, field).unwrap();
+            }
+            writer.write(64, 256u64).unwrap();
+            write_symbol_table(&mut writer, 257, [(97, count)]).unwrap();
+            Gamma::encode_number(rule_len, &mut writer).unwrap();
+            writer.byte_align().unwrap();
+            writer.write(64, STATE_LOW).unwrap();
+            buf
+        };
+        let huge = u32::MAX as u64;
+        assert!(
+            matches!(GrammarAnsCoder::decode(header(huge, huge).as_slice()), Err(RReaderError::IO(_))),
+            "Symbols decoded without reading input"
+        );
+        assert!(
+            matches!(GrammarAnsCoder::decode(header(1, huge).as_slice()), Err(RReaderError::IO(_))),
+            "Rule lengths not matching the symbol counts decoded"
+        );
+
+        let mut buf = encode::<GrammarAnsCoder>(&Grammar::empty());
+        buf[0] = b'X';
+        assert!(
+            matches!(
+                GrammarAnsCoder::decode(buf.as_slice()),
+                Err(RReaderError::UnsupportedFormat { version: None, .. })
+            ),
+            "File without the magic bytes decoded"
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn full_integer_alphabet_test() {
+        // The largest integer makes the alphabet one larger than 32 bits can hold
+        let symbols = [u32::MAX, 0, u32::MAX, 0];
+        let gr = native_repair::repair_ints(&symbols);
+        assert_eq!(1 << 32, gr.alphabet_size(), "Alphabet not covering the largest integer");
+        let decoded = GrammarAnsCoder::decode(encode::<GrammarAnsCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Grammar over the full range of integers decoded incorrectly"
+        );
+    }
+}
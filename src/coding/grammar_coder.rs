@@ -1,6 +1,8 @@
 use std::io::Write;
 
-use crate::grammar::Grammar;
+use bitstream_io::{BitRead, BitWrite};
+
+use crate::{error::RReaderError, grammar::Grammar};
 
 pub trait GrammarEncoder {
     type EncodeErr;
@@ -11,3 +13,54 @@ pub trait GrammarDecoder<I> {
     type DecodeErr;
     fn decode(input: I) -> Result<Grammar, Self::DecodeErr>;
 }
+
+/// Estimates how many bits an encoding takes for the parts of a grammar, so it can be optimized for it
+pub trait CostModel {
+    /// The bits needed for one occurrence of the symbol on a right-hand side
+    fn symbol_cost(grammar: &Grammar, symbol: usize) -> u64;
+    /// The bits needed for each rule apart from its symbols, like its length
+    fn rule_cost(grammar: &Grammar) -> u64;
+}
+
+/// Writes the magic bytes identifying a binary format, followed by its version as a 32-bit big-endian number
+pub(super) fn write_magic(out: &mut impl BitWrite, magic: &[u8; 4], version: u32) -> std::io::Result<()> {
+    out.write_bytes(magic)?;
+    out.write_bytes(&version.to_be_bytes())
+}
+
+/// Reads the magic bytes and version written by [`write_magic`], failing if they are not those of the format
+pub(super) fn read_magic(
+    input: &mut impl BitRead,
+    magic: &[u8; 4],
+    version: u32,
+    format: &'static str,
+) -> Result<(), RReaderError> {
+    let mut buf = [0u8; 4];
+    input.read_bytes(&mut buf)?;
+    if &buf != magic {
+        return Err(RReaderError::UnsupportedFormat { format, version: None });
+    }
+    read_version(input, version, format)
+}
+
+/// Writes the alphabet size as a 64-bit big-endian number, as an alphabet of integers can have 2^32 terminals
+pub(super) fn write_alphabet_size(out: &mut impl BitWrite, alphabet_size: usize) -> std::io::Result<()> {
+    out.write_bytes(&(alphabet_size as u64).to_be_bytes())
+}
+
+/// Reads the alphabet size written by [`write_alphabet_size`], failing if it does not fit into a usize
+pub(super) fn read_alphabet_size(input: &mut impl BitRead) -> Result<usize, RReaderError> {
+    let mut buf = [0u8; 8];
+    input.read_bytes(&mut buf)?;
+    usize::try_from(u64::from_be_bytes(buf)).map_err(|_| RReaderError::TooLarge("alphabet size"))
+}
+
+/// Reads the version following the magic bytes, failing if it is not the supported one
+pub(super) fn read_version(input: &mut impl BitRead, version: u32, format: &'static str) -> Result<(), RReaderError> {
+    let mut buf = [0u8; 4];
+    input.read_bytes(&mut buf)?;
+    match u32::from_be_bytes(buf) {
+        found if found == version => Ok(()),
+        found => Err(RReaderError::UnsupportedFormat { format, version: Some(found) }),
+    }
+}
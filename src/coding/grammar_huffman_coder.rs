@@ -0,0 +1,430 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    io::{Error, Read},
+};
+
+use bitstream_io::{BigEndian, BitRead, BitReader, BitWrite, BitWriter};
+
+use crate::{error::RReaderError, grammar::Grammar};
+
+use super::{
+    grammar_coder::{read_alphabet_size, read_magic, write_alphabet_size, write_magic, GrammarDecoder, GrammarEncoder},
+    grammar_tuple_coder::{to_u32, MAX_PREALLOCATION},
+    number_coder::{invalid, Gamma, NumberDecoder, NumberEncoder},
+};
+
+/// The longest code a symbol may get. Frequencies are flattened until all codes fit,
+/// which always happens for fewer than 2^38 distinct symbols.
+const MAX_CODE_LEN: u32 = 40;
+
+/// Writes the symbols of the renumbered grammar with a canonical Huffman code over their frequencies in the rules.
+///
+/// The file starts with the magic bytes `RRGH` and the format version,
+/// followed by the rule count and the minimum rule length as 32-bit numbers and the alphabet size as a 64-bit number.
+/// They are followed by the code length of each symbol, where terminals come first and rule `i` is symbol `alphabet_size + i`.
+/// Each length is written in gamma code, except that a 0 is followed by the number of unused symbols in a row minus one.
+/// Then each rule follows as its length minus the minimum in gamma code and the codes of its symbols.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GrammarHuffmanCoder;
+
+const MAGIC: &[u8; 4] = b"RRGH";
+const VERSION: u32 = 2;
+
+impl GrammarEncoder for GrammarHuffmanCoder {
+    type EncodeErr = std::io::Error;
+
+    fn encode<Out: std::io::Write>(mut grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
+        let mut bit_writer = BitWriter::endian(out, BigEndian);
+        grammar.prune();
+        grammar.renumber();
+
+        let symbol_count = grammar.alphabet_size() as u64 + grammar.rule_count() as u64;
+        let mut frequencies = BTreeMap::new();
+        for &symbol in grammar.iter().flatten() {
+            *frequencies.entry(symbol).or_insert(0u64) += 1;
+        }
+        let lengths = code_lengths(&frequencies.values().copied().collect::<Vec<_>>());
+        let code_lengths = frequencies.keys().copied().zip(lengths).collect::<Vec<_>>();
+        let codes = canonical_codes(&code_lengths).into_iter().collect::<BTreeMap<_, _>>();
+
+        let min_len = grammar.iter().map(Vec::len).min().unwrap_or(0);
+        write_magic(&mut bit_writer, MAGIC, VERSION)?;
+        for (value, what) in [(grammar.rule_count(), "rule count"), (min_len, "rule length")] {
+            bit_writer.write_bytes(&to_u32(value, what)?.to_be_bytes())?;
+        }
+        write_alphabet_size(&mut bit_writer, grammar.alphabet_size())?;
+
+        let table = code_lengths.iter().map(|&(symbol, len)| (symbol, len as u64));
+        write_symbol_table(&mut bit_writer, symbol_count, table)?;
+
+        for rule in grammar.iter() {
+            Gamma::encode_number((rule.len() - min_len) as u64, &mut bit_writer)?;
+            for symbol in rule {
+                let (len, code) = codes[symbol];
+                bit_writer.write(len, code)?;
+            }
+        }
+
+        // Make sure any remaining bits are also written out
+        bit_writer.byte_align()?;
+        bit_writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<I> GrammarDecoder<I> for GrammarHuffmanCoder
+where
+    I: Read,
+{
+    type DecodeErr = RReaderError;
+
+    fn decode(input: I) -> Result<Grammar, Self::DecodeErr> {
+        let mut bit_reader = BitReader::endian(input, BigEndian);
+        read_magic(&mut bit_reader, MAGIC, VERSION, "huffman")?;
+        let mut header = [0usize; 2];
+        for field in header.iter_mut() {
+            let mut buf32 = [0u8; 4];
+            bit_reader.read_bytes(&mut buf32)?;
+            *field = u32::from_be_bytes(buf32) as usize;
+        }
+        let [rule_count, min_len] = header;
+        let alphabet_size = read_alphabet_size(&mut bit_reader)?;
+
+        let symbol_count = alphabet_size.checked_add(rule_count).ok_or(RReaderError::TooLarge("alphabet size"))? as u64;
+        let code_lengths = read_symbol_table(&mut bit_reader, symbol_count, MAX_CODE_LEN as u64)?
+            .into_iter()
+            .map(|(symbol, len)| (symbol, len as u32))
+            .collect::<Vec<_>>();
+        let decoder = HuffmanDecoder::new(&code_lengths)?;
+
+        let mut rules = Vec::with_capacity(rule_count.min(MAX_PREALLOCATION));
+        for _ in 0..rule_count {
+            let rule_len = usize::try_from(Gamma::decode_number(&mut bit_reader)?)
+                .ok()
+                .and_then(|len| len.checked_add(min_len))
+                .ok_or(RReaderError::TooLarge("rule length"))?;
+            let mut rule = Vec::with_capacity(rule_len.min(MAX_PREALLOCATION));
+            for _ in 0..rule_len {
+                rule.push(decoder.decode_symbol(&mut bit_reader)?);
+            }
+            rules.push(rule);
+        }
+
+        let grammar = Grammar::from_parts_with_alphabet(rules, rule_count.saturating_sub(1), alphabet_size);
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
+
+/// Writes a positive value for some of the symbols `0..symbol_count`, which must be given in increasing order.
+/// Each value is written in gamma code. Symbols without one are written as a 0 followed by the number of them in a row minus one.
+pub(super) fn write_symbol_table(
+    writer: &mut impl BitWrite,
+    symbol_count: u64,
+    table: impl IntoIterator<Item = (usize, u64)>,
+) -> std::io::Result<()> {
+    let mut next = 0;
+    for (symbol, value) in table {
+        write_unused(writer, symbol as u64 - next)?;
+        Gamma::encode_number(value, writer)?;
+        next = symbol as u64 + 1;
+    }
+    write_unused(writer, symbol_count - next)
+}
+
+/// Reads the table written by [`write_symbol_table`], rejecting values above `max_value`
+pub(super) fn read_symbol_table(
+    reader: &mut impl BitRead,
+    symbol_count: u64,
+    max_value: u64,
+) -> std::io::Result<Vec<(usize, u64)>> {
+    let mut table = vec![];
+    let mut next = 0;
+    while next < symbol_count {
+        let value = Gamma::decode_number(reader)?;
+        if value == 0 {
+            let unused = Gamma::decode_number(reader)? + 1;
+            if unused > symbol_count - next {
+                return Err(invalid("more unused symbols than symbols"));
+            }
+            next += unused;
+        } else if value > max_value {
+            return Err(invalid("symbol table value too large"));
+        } else {
+            table.push((next as usize, value));
+            next += 1;
+        }
+    }
+    Ok(table)
+}
+
+/// Writes a run of unused symbols, if there are any
+fn write_unused(writer: &mut impl BitWrite, unused: u64) -> std::io::Result<()> {
+    if unused == 0 {
+        return Ok(());
+    }
+    Gamma::encode_number(0, writer)?;
+    Gamma::encode_number(unused - 1, writer)
+}
+
+/// The lengths of a Huffman code for the given frequencies, none of which is longer than [`MAX_CODE_LEN`].
+/// A single symbol gets a code of length 1.
+fn code_lengths(frequencies: &[u64]) -> Vec<u32> {
+    if frequencies.len() == 1 {
+        return vec![1];
+    }
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|&len| len <= MAX_CODE_LEN) {
+            return lengths;
+        }
+        // Halving the frequencies evens them out, which makes the deepest codes shorter
+        for frequency in frequencies.iter_mut() {
+            *frequency = *frequency / 2 + 1;
+        }
+    }
+}
+
+/// The depths of the leaves of a Huffman tree for the given frequencies
+fn huffman_lengths(frequencies: &[u64]) -> Vec<u32> {
+    let leaves = frequencies.len();
+    let mut heap = frequencies
+        .iter()
+        .enumerate()
+        .map(|(node, &frequency)| Reverse((frequency, node)))
+        .collect::<BinaryHeap<_>>();
+    // The parent of each node. Inner nodes are numbered after the leaves in the order they are created.
+    let mut parents = vec![0; leaves.saturating_mul(2).saturating_sub(1)];
+    let mut next_node = leaves;
+    while let (Some(Reverse((first, a))), Some(Reverse((second, b)))) = (heap.pop(), heap.pop()) {
+        parents[a] = next_node;
+        parents[b] = next_node;
+        heap.push(Reverse((first + second, next_node)));
+        next_node += 1;
+    }
+
+    // Parents are created after their children, so their depth is known when going backwards. The root is the last node.
+    let mut depths = vec![0; parents.len()];
+    for node in (0..parents.len().saturating_sub(1)).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    depths.truncate(leaves);
+    depths
+}
+
+/// Assigns each symbol its canonical code, given as its length and value.
+/// Codes are handed out in order of length, and of symbol within the same length.
+fn canonical_codes(code_lengths: &[(usize, u32)]) -> Vec<(usize, (u32, u64))> {
+    let mut sorted = code_lengths.to_vec();
+    sorted.sort_unstable_by_key(|&(symbol, len)| (len, symbol));
+    let mut code = 0u64;
+    let mut prev_len = 0;
+    sorted
+        .into_iter()
+        .map(|(symbol, len)| {
+            code <<= len - prev_len;
+            prev_len = len;
+            code += 1;
+            (symbol, (len, code - 1))
+        })
+        .collect()
+}
+
+/// Decodes canonical codes bit by bit, by tracking the first code of each length
+struct HuffmanDecoder {
+    /// The number of codes of each length
+    counts: Vec<u64>,
+    /// The symbols in order of their codes
+    symbols: Vec<usize>,
+}
+
+impl HuffmanDecoder {
+    fn new(code_lengths: &[(usize, u32)]) -> Result<Self, Error> {
+        let mut counts = vec![0u64; MAX_CODE_LEN as usize + 1];
+        for &(_, len) in code_lengths {
+            counts[len as usize] += 1;
+        }
+        // The codes would overlap if the lengths violate Kraft's inequality
+        let kraft_sum = (1..=MAX_CODE_LEN)
+            .map(|len| (counts[len as usize] as u128) << (MAX_CODE_LEN - len))
+            .sum::<u128>();
+        if kraft_sum > 1 << MAX_CODE_LEN {
+            return Err(invalid("code lengths do not form a prefix code"));
+        }
+        let symbols = canonical_codes(code_lengths)
+            .into_iter()
+            .map(|(symbol, _)| symbol)
+            .collect();
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode_symbol(&self, reader: &mut impl BitRead) -> std::io::Result<usize> {
+        let mut code = 0u64;
+        let mut first = 0u64;
+        let mut index = 0u64;
+        for len in 1..=MAX_CODE_LEN as usize {
+            code |= reader.read_bit()? as u64;
+            let count = self.counts[len];
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("unknown code"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coding::{
+            grammar_coder::GrammarDecoder,
+            grammar_packed_coder::GrammarPackedCoder,
+            test_util::{encode, repetitive_text},
+        },
+        error::RReaderError,
+        grammar::Grammar,
+        native_repair,
+    };
+    use bitstream_io::{BigEndian, BitWrite, BitWriter};
+
+    use super::{canonical_codes, code_lengths, GrammarHuffmanCoder, MAGIC, MAX_CODE_LEN, VERSION};
+
+    #[test]
+    fn coding_decoding_test() {
+        let mut gr = Grammar::from_parts(
+            vec![
+                vec![257, 258, 100],
+                vec![97, 98, 99],
+                vec![100, 101, 259],
+                vec![102, 103, 104, 257],
+            ],
+            0,
+        );
+        let decoded = GrammarHuffmanCoder::decode(encode::<GrammarHuffmanCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+
+        // The read grammar will be renumbered as it is required by the coder
+        gr.renumber();
+        assert_eq!(gr, decoded.unwrap(), "Resulting grammar differs from original grammar");
+    }
+
+    #[test]
+    fn sparse_alphabet_test() {
+        // Few terminals spread over a huge alphabet
+        let mut gr = Grammar::from_parts_with_alphabet(
+            vec![vec![4_000_000_000, 7], vec![4_000_000_001, 4_000_000_001, 0, 5]],
+            1,
+            4_000_000_001,
+        );
+        let decoded = GrammarHuffmanCoder::decode(encode::<GrammarHuffmanCoder>(&gr).as_slice());
+        gr.renumber();
+        assert_eq!(Some(gr), decoded.ok(), "Grammar over a sparse alphabet not decoded correctly");
+    }
+
+    #[test]
+    fn few_symbols_test() {
+        for gr in [
+            Grammar::empty(),
+            Grammar::from_parts(vec![vec![]], 0),
+            Grammar::from_parts(vec![vec![97]], 0),
+            Grammar::from_parts(vec![vec![97, 97, 97]], 0),
+        ] {
+            let decoded = GrammarHuffmanCoder::decode(encode::<GrammarHuffmanCoder>(&gr).as_slice());
+            assert_eq!(Some(gr), decoded.ok(), "Grammar with few symbols not decoded correctly");
+        }
+    }
+
+    #[test]
+    fn repair_test() {
+        let text = repetitive_text();
+        let gr = native_repair::repair_bytes(&text);
+        let huffman = encode::<GrammarHuffmanCoder>(&gr);
+        let decoded = GrammarHuffmanCoder::decode(huffman.as_slice()).unwrap();
+        assert_eq!(text, decoded.bytes().collect::<Vec<_>>(), "Huffman-coded grammar producing the wrong text");
+        assert!(
+            huffman.len() < encode::<GrammarPackedCoder>(&gr).len(),
+            "Huffman encoding not smaller than the packed encoding"
+        );
+    }
+
+    #[test]
+    fn code_lengths_test() {
+        assert_eq!(vec![1], code_lengths(&[5]), "Single symbol not given a code");
+        assert_eq!(vec![1, 2, 3, 3], code_lengths(&[8, 4, 2, 1]), "Huffman code lengths incorrect");
+        assert_eq!(
+            vec![(1, (1, 0b0)), (0, (2, 0b10)), (2, (2, 0b11))],
+            canonical_codes(&[(0, 2), (1, 1), (2, 2)]),
+            "Canonical codes incorrect"
+        );
+
+        // Fibonacci frequencies lead to a code as deep as there are symbols, unless it is limited
+        let mut fibonacci = vec![1u64, 1];
+        while fibonacci.len() < 60 {
+            fibonacci.push(fibonacci[fibonacci.len() - 1] + fibonacci[fibonacci.len() - 2]);
+        }
+        let lengths = code_lengths(&fibonacci);
+        assert!(lengths.iter().all(|&len| len <= MAX_CODE_LEN), "Code lengths not limited");
+        let kraft_sum = lengths.iter().map(|&len| 1u128 << (MAX_CODE_LEN - len)).sum::<u128>();
+        assert_eq!(1 << MAX_CODE_LEN, kraft_sum, "Limited code lengths not complete");
+    }
+
+    #[test]
+    fn corrupt_test() {
+        // Two rules over 2 terminals, where both terminals get a code of length 1 and rule 0 a code of length 1
+        let mut buf = vec![];
+        let mut writer = BitWriter::endian(&mut buf, BigEndian);
+        writer.write_bytes(MAGIC).unwrap();
+        for field in [VERSION, 2, 1] {
+            writer.write(32, field).unwrap();
+        }
+        writer.write(64, 2u64).unwrap();
+        // Gamma codes of 1 for both terminals and rule 0, then 0 followed by 0 for the unused rule 1
+        for _ in 0..3 {
+            writer.write(3, 0b010u32).unwrap();
+        }
+        writer.write(2, 0b11u32).unwrap();
+        writer.byte_align().unwrap();
+        assert!(
+            matches!(GrammarHuffmanCoder::decode(buf.as_slice()), Err(RReaderError::IO(_))),
+            "Overlapping codes decoded"
+        );
+
+        let buf = encode::<GrammarHuffmanCoder>(&Grammar::from_parts(vec![vec![97, 98, 99, 100, 101]], 0));
+        assert!(
+            matches!(GrammarHuffmanCoder::decode(&buf[..buf.len() - 1]), Err(RReaderError::IO(_))),
+            "Truncated file decoded"
+        );
+
+        let mut buf = encode::<GrammarHuffmanCoder>(&Grammar::empty());
+        buf[7] ^= 1;
+        assert!(
+            matches!(
+                GrammarHuffmanCoder::decode(buf.as_slice()),
+                Err(RReaderError::UnsupportedFormat { version: Some(_), .. })
+            ),
+            "File of another version decoded"
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn full_integer_alphabet_test() {
+        // The largest integer makes the alphabet one larger than 32 bits can hold
+        let symbols = [u32::MAX, 0, u32::MAX, 0];
+        let gr = native_repair::repair_ints(&symbols);
+        assert_eq!(1 << 32, gr.alphabet_size(), "Alphabet not covering the largest integer");
+        let decoded = GrammarHuffmanCoder::decode(encode::<GrammarHuffmanCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Grammar over the full range of integers decoded incorrectly"
+        );
+    }
+}
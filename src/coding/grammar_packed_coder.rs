@@ -0,0 +1,291 @@
+use std::io::Read;
+
+use bitstream_io::{BigEndian, BitRead, BitReader, BitWrite, BitWriter};
+
+use crate::{error::RReaderError, grammar::Grammar};
+
+use super::{
+    grammar_coder::{read_alphabet_size, read_magic, write_alphabet_size, write_magic, CostModel, GrammarDecoder, GrammarEncoder},
+    grammar_tuple_coder::{to_u32, MAX_PREALLOCATION},
+    number_coder::{bits_for, invalid, read_bits, write_bits},
+};
+
+/// Like [`super::grammar_tuple_coder::GrammarTupleCoder`], but with every number packed into as few bits as its range needs.
+/// The file starts with the magic bytes `RRGP` and the format version,
+/// followed by the same header fields: the rule count and the minimum and maximum rule length as 32-bit numbers
+/// and the alphabet size as a 64-bit number.
+/// Each rule length minus the minimum takes `ceil(log2(max_len - min_len + 1))` bits, which is none if all rules have the same length.
+/// Each symbol takes `ceil(log2(alphabet_size + rule_count))` bits, so no flag is needed to tell terminals and nonterminals apart.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GrammarPackedCoder;
+
+const MAGIC: &[u8; 4] = b"RRGP";
+const VERSION: u32 = 2;
+
+impl GrammarEncoder for GrammarPackedCoder {
+    type EncodeErr = std::io::Error;
+
+    fn encode<Out: std::io::Write>(mut grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
+        let mut bit_writer = BitWriter::endian(out, BigEndian);
+        grammar.prune();
+        grammar.renumber();
+
+        let alphabet_size = grammar.alphabet_size();
+        let symbol_bits = bits_for(alphabet_size.saturating_add(grammar.rule_count()));
+        let (rules, _) = grammar.consume();
+
+        let min_len = rules.iter().map(Vec::len).min().unwrap_or(0);
+        let max_len = rules.iter().map(Vec::len).max().unwrap_or(0);
+        let len_bits = bits_for(max_len - min_len + 1);
+
+        write_magic(&mut bit_writer, MAGIC, VERSION)?;
+        for (value, what) in [
+            (rules.len(), "rule count"),
+            (min_len, "rule length"),
+            (max_len, "rule length"),
+        ] {
+            bit_writer.write_bytes(&to_u32(value, what)?.to_be_bytes())?;
+        }
+        write_alphabet_size(&mut bit_writer, alphabet_size)?;
+
+        for rule in rules {
+            write_bits(&mut bit_writer, len_bits, (rule.len() - min_len) as u64)?;
+            for symbol in rule {
+                write_bits(&mut bit_writer, symbol_bits, symbol as u64)?;
+            }
+        }
+
+        // Make sure any remaining bits are also written out
+        bit_writer.byte_align()?;
+        bit_writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<I> GrammarDecoder<I> for GrammarPackedCoder
+where
+    I: Read,
+{
+    type DecodeErr = RReaderError;
+
+    fn decode(input: I) -> Result<Grammar, Self::DecodeErr> {
+        let mut bit_reader = BitReader::endian(input, BigEndian);
+        read_magic(&mut bit_reader, MAGIC, VERSION, "packed")?;
+        let mut header = [0usize; 3];
+        for field in header.iter_mut() {
+            let mut buf32 = [0u8; 4];
+            bit_reader.read_bytes(&mut buf32)?;
+            *field = u32::from_be_bytes(buf32) as usize;
+        }
+        let [rule_count, min_len, max_len] = header;
+        let alphabet_size = read_alphabet_size(&mut bit_reader)?;
+        if min_len > max_len {
+            return Err(RReaderError::TooLarge("minimum rule length"));
+        }
+        let symbol_bits = bits_for(alphabet_size.saturating_add(rule_count));
+        let len_bits = bits_for(max_len - min_len + 1);
+        // Every symbol and every rule but an empty start rule has to take up input, or the header could make
+        // the loops below run for billions of iterations without reading anything
+        if symbol_bits == 0 && max_len > 0 {
+            return Err(invalid("rules have symbols, but there is no symbol they can be").into());
+        }
+        if max_len == 0 && rule_count > 1 {
+            return Err(invalid("all rules are empty, but only the start rule may be").into());
+        }
+
+        let mut rules = Vec::with_capacity(rule_count.min(MAX_PREALLOCATION));
+        for _ in 0..rule_count {
+            let rule_len = read_bits(&mut bit_reader, len_bits)? as usize + min_len;
+            let mut rule = Vec::with_capacity(rule_len.min(MAX_PREALLOCATION));
+            for _ in 0..rule_len {
+                rule.push(read_bits(&mut bit_reader, symbol_bits)? as usize);
+            }
+            rules.push(rule);
+        }
+
+        let grammar = Grammar::from_parts_with_alphabet(rules, rule_count.saturating_sub(1), alphabet_size);
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
+
+impl CostModel for GrammarPackedCoder {
+    fn symbol_cost(grammar: &Grammar, _symbol: usize) -> u64 {
+        bits_for(grammar.alphabet_size().saturating_add(grammar.rule_count())) as u64
+    }
+
+    fn rule_cost(grammar: &Grammar) -> u64 {
+        // The rule length
+        let min_len = grammar.iter().map(Vec::len).min().unwrap_or(0);
+        let max_len = grammar.iter().map(Vec::len).max().unwrap_or(0);
+        bits_for(max_len - min_len + 1) as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coding::{
+            grammar_coder::GrammarDecoder,
+            grammar_tuple_coder::GrammarTupleCoder,
+            test_util::{encode, repetitive_text},
+        },
+        error::{GrammarError, RReaderError},
+        grammar::Grammar,
+        native_repair,
+    };
+    use bitstream_io::{BigEndian, BitWrite, BitWriter};
+
+    use super::{GrammarPackedCoder, MAGIC, VERSION};
+
+    #[test]
+    fn coding_decoding_test() {
+        let mut gr = Grammar::from_parts(
+            vec![
+                vec![257, 258, 100],
+                vec![97, 98, 99],
+                vec![100, 101, 259],
+                vec![102, 103, 104, 257],
+            ],
+            0,
+        );
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+
+        // The read grammar will be renumbered as it is required by the coder
+        gr.renumber();
+        assert_eq!(gr, decoded.unwrap(), "Resulting grammar differs from original grammar");
+    }
+
+    #[test]
+    fn integer_alphabet_test() {
+        let mut gr = Grammar::from_parts_with_alphabet(
+            vec![vec![70000, 3], vec![100000, 100000, 99999], vec![100001, 0, 100000]],
+            2,
+            100000,
+        );
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&gr).as_slice());
+        gr.renumber();
+        assert_eq!(Some(gr), decoded.ok(), "Grammar over integers not decoded correctly");
+    }
+
+    #[test]
+    fn empty_test() {
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&Grammar::empty()).as_slice());
+        assert_eq!(Some(Grammar::empty()), decoded.ok(), "Empty grammar not decoded correctly");
+    }
+
+    #[test]
+    fn repair_test() {
+        let text = repetitive_text();
+        let gr = native_repair::repair_bytes(&text);
+        let packed = encode::<GrammarPackedCoder>(&gr);
+        let decoded = GrammarPackedCoder::decode(packed.as_slice()).unwrap();
+        assert_eq!(text, decoded.bytes().collect::<Vec<_>>(), "Packed grammar producing the wrong text");
+        assert!(
+            packed.len() < encode::<GrammarTupleCoder>(&gr).len(),
+            "Packed encoding not smaller than the tuple encoding"
+        );
+
+        // All rules of a straight-line program but the start rule have length 2, so their lengths take no bits
+        let slp = gr.to_slp(true).unwrap();
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&slp).as_slice()).unwrap();
+        assert_eq!(text, decoded.bytes().collect::<Vec<_>>(), "Packed SLP producing the wrong text");
+    }
+
+    #[test]
+    fn inline_test() {
+        let text = (0..5000usize).map(|i| b'a' + (i * i % 17) as u8).collect::<Vec<_>>();
+        let gr = native_repair::repair_bytes(&text);
+        let mut inlined = gr.clone();
+        inlined.inline::<GrammarPackedCoder>(usize::MAX);
+        assert!(
+            encode::<GrammarPackedCoder>(&inlined).len() <= encode::<GrammarPackedCoder>(&gr).len(),
+            "Inlining made the packed encoding larger"
+        );
+        assert_eq!(text, inlined.bytes().collect::<Vec<_>>(), "Inlining changed the text");
+    }
+
+    #[test]
+    fn corrupt_test() {
+        // One rule of 1 symbol over 4 terminals, so symbols take 3 bits. The symbol 5 refers to rule 1, which does not exist.
+        let mut buf = vec![];
+        let mut writer = BitWriter::endian(&mut buf, BigEndian);
+        writer.write_bytes(MAGIC).unwrap();
+        for field in [VERSION, 1, 1, 1] {
+            writer.write(32, field).unwrap();
+        }
+        writer.write(64, 4u64).unwrap();
+        writer.write(3, 5u32).unwrap();
+        writer.byte_align().unwrap();
+        assert!(
+            matches!(
+                GrammarPackedCoder::decode(buf.as_slice()),
+                Err(RReaderError::InvalidGrammar(GrammarError::DanglingReference { rule: 0, symbol: 5 }))
+            ),
+            "Dangling reference decoded"
+        );
+
+        let buf = encode::<GrammarPackedCoder>(&Grammar::from_parts(vec![vec![97, 98, 99]], 0));
+        assert!(
+            matches!(GrammarPackedCoder::decode(&buf[..buf.len() - 1]), Err(RReaderError::IO(_))),
+            "Truncated file decoded"
+        );
+    }
+
+    #[test]
+    fn header_without_data_test() {
+        // Each header claims billions of rules or symbols, which would take no bits at all
+        for [rule_count, min_len, max_len, alphabet_size] in [
+            [u32::MAX, 0, 0, 0],
+            [u32::MAX, 0, 0, 256],
+            [1, 0, u32::MAX, 0],
+            [1, u32::MAX, u32::MAX, 0],
+        ] {
+            let mut buf = vec![];
+            let mut writer = BitWriter::endian(&mut buf, BigEndian);
+            writer.write_bytes(MAGIC).unwrap();
+            for field in [VERSION, rule_count, min_len, max_len] {
+                writer.write(32, field).unwrap();
+            }
+            writer.write(64, alphabet_size as u64).unwrap();
+            writer.write(32, u32::MAX).unwrap();
+            assert!(
+                matches!(GrammarPackedCoder::decode(buf.as_slice()), Err(RReaderError::IO(_))),
+                "Header of {rule_count} rules of {min_len} to {max_len} symbols over {alphabet_size} terminals decoded"
+            );
+        }
+
+        // The empty sequence of integers has a single empty rule and no terminals, so it takes no bits
+        let gr = native_repair::repair_ints(&[]);
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&gr).as_slice());
+        assert_eq!(Some(gr), decoded.ok(), "Empty sequence of integers not decoded correctly");
+
+        let mut buf = encode::<GrammarPackedCoder>(&Grammar::empty());
+        buf[0] = b'X';
+        assert!(
+            matches!(
+                GrammarPackedCoder::decode(buf.as_slice()),
+                Err(RReaderError::UnsupportedFormat { version: None, .. })
+            ),
+            "File without the magic bytes decoded"
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn full_integer_alphabet_test() {
+        // The largest integer makes the alphabet one larger than 32 bits can hold
+        let symbols = [u32::MAX, 0, u32::MAX, 0];
+        let gr = native_repair::repair_ints(&symbols);
+        assert_eq!(1 << 32, gr.alphabet_size(), "Alphabet not covering the largest integer");
+        let decoded = GrammarPackedCoder::decode(encode::<GrammarPackedCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Grammar over the full range of integers decoded incorrectly"
+        );
+    }
+}
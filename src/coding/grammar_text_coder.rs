@@ -0,0 +1,315 @@
+use std::io::{Read, Write};
+
+use crate::{
+    error::RReaderError,
+    grammar::{Grammar, TextOptions, RULE_OFFSET},
+};
+
+use super::grammar_coder::{GrammarDecoder, GrammarEncoder};
+
+/// A human-readable format, consisting of a header and the rules as written by [`Grammar::write_text`]:
+///
+/// ```text
+/// alphabet 256
+/// start R0
+/// R0 -> R1 R1 '!'
+/// R1 -> 'a' 'b' # comments run to the end of the line
+/// ```
+///
+/// When decoding, the header lines are optional and default to an alphabet of 256 and `R0` as the start rule.
+/// The rules must be numbered consecutively from `R0`. Terminals may be written as quoted bytes or as numbers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GrammarTextCoder;
+
+impl GrammarEncoder for GrammarTextCoder {
+    type EncodeErr = std::io::Error;
+
+    fn encode<Out: Write>(mut grammar: Grammar, mut out: Out) -> Result<(), Self::EncodeErr> {
+        grammar.prune();
+        writeln!(out, "alphabet {}", grammar.alphabet_size())?;
+        if grammar.rule_count() > 0 {
+            writeln!(out, "start R{}", grammar.start_rule())?;
+        }
+        grammar.write_text(&mut out, &TextOptions::default())?;
+        out.flush()
+    }
+}
+
+impl<I> GrammarDecoder<I> for GrammarTextCoder
+where
+    I: Read,
+{
+    type DecodeErr = RReaderError;
+
+    fn decode(mut input: I) -> Result<Grammar, Self::DecodeErr> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+
+        let mut alphabet_size = None;
+        let mut start_rule = None;
+        let mut rules = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let error = |reason: String| RReaderError::Parse { line: i + 1, reason };
+            let mut tokens = tokenize(line).map_err(error)?.into_iter();
+            match tokens.next() {
+                None => continue,
+                Some(Token::Word(word)) if word == "alphabet" => {
+                    let size = match (tokens.next(), tokens.next()) {
+                        (Some(Token::Number(size)), None) => size,
+                        _ => return Err(error("expected the alphabet size".to_owned())),
+                    };
+                    // The alphabet size determines which symbols the rules refer to
+                    if alphabet_size.is_some() || !rules.is_empty() {
+                        return Err(error("the alphabet size must be given once before the rules".to_owned()));
+                    }
+                    alphabet_size = Some(size);
+                }
+                Some(Token::Word(word)) if word == "start" => {
+                    let rule = match (tokens.next(), tokens.next()) {
+                        (Some(Token::Rule(rule)), None) => rule,
+                        _ => return Err(error("expected the start rule".to_owned())),
+                    };
+                    if start_rule.replace(rule).is_some() {
+                        return Err(error("duplicate start rule".to_owned()));
+                    }
+                }
+                Some(Token::Rule(rule)) => {
+                    if rule != rules.len() {
+                        return Err(error(format!("expected R{}, found R{rule}", rules.len())));
+                    }
+                    if tokens.next() != Some(Token::Arrow) {
+                        return Err(error("expected -> after the rule".to_owned()));
+                    }
+                    let alphabet_size = *alphabet_size.get_or_insert(RULE_OFFSET);
+                    let symbols = tokens
+                        .map(|token| match token {
+                            Token::Rule(id) => alphabet_size
+                                .checked_add(id)
+                                .ok_or_else(|| error(format!("rule R{id} is too large"))),
+                            Token::Byte(byte) if (byte as usize) < alphabet_size => Ok(byte as usize),
+                            Token::Number(terminal) if terminal < alphabet_size => Ok(terminal),
+                            Token::Byte(_) | Token::Number(_) => Err(error(format!(
+                                "terminal {token:?} is not in the alphabet of size {alphabet_size}"
+                            ))),
+                            Token::Word(_) | Token::Arrow => Err(error(format!("unexpected {token:?} in rule"))),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    rules.push(symbols);
+                }
+                Some(token) => return Err(error(format!("unexpected {token:?} at the start of the line"))),
+            }
+        }
+
+        let alphabet_size = alphabet_size.unwrap_or(RULE_OFFSET);
+        let grammar = Grammar::from_parts_with_alphabet(rules, start_rule.unwrap_or(0), alphabet_size);
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A keyword like `alphabet`
+    Word(String),
+    /// A nonterminal `R<i>`
+    Rule(usize),
+    /// A terminal written as a number
+    Number(usize),
+    /// A terminal written as a quoted byte
+    Byte(u8),
+    Arrow,
+}
+
+/// Splits a line into tokens, up to a `#` outside of quotes
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '\'' {
+            chars.next();
+            tokens.push(Token::Byte(parse_byte(&mut chars)?));
+            if chars.next().map(|(_, c)| c) != Some('\'') {
+                return Err("expected ' to close the terminal".to_owned());
+            }
+        } else if line[start..].starts_with("->") {
+            chars.nth(1);
+            tokens.push(Token::Arrow);
+        } else if c.is_ascii_alphanumeric() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &line[start..end];
+            let number = |digits: &str| {
+                digits
+                    .parse::<usize>()
+                    .map_err(|_| format!("{word} is not a valid number"))
+            };
+            tokens.push(match word.strip_prefix('R') {
+                Some(digits) if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+                    Token::Rule(number(digits)?)
+                }
+                _ if c.is_ascii_digit() => Token::Number(number(word)?),
+                _ => Token::Word(word.to_owned()),
+            });
+        } else {
+            return Err(format!("unexpected character {c:?}"));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a byte as written by [`Grammar::write_text`], without the quotes
+fn parse_byte(chars: &mut impl Iterator<Item = (usize, char)>) -> Result<u8, String> {
+    let mut next = || chars.next().map(|(_, c)| c).ok_or("terminal ended early".to_owned());
+    match next()? {
+        '\\' => match next()? {
+            'n' => Ok(b'\n'),
+            'r' => Ok(b'\r'),
+            't' => Ok(b'\t'),
+            c @ ('\\' | '\'' | '"') => Ok(c as u8),
+            'x' => {
+                let digits = [next()?, next()?].iter().collect::<String>();
+                u8::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape \\x{digits}"))
+            }
+            c => Err(format!("unknown escape \\{c}")),
+        },
+        c if c.is_ascii() => Ok(c as u8),
+        c => Err(format!("{c:?} is not a byte, write it as \\xNN escapes or a number")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coding::grammar_coder::{GrammarDecoder, GrammarEncoder},
+        error::{GrammarError, RReaderError},
+        grammar::Grammar,
+        native_repair,
+    };
+
+    use super::GrammarTextCoder;
+
+    fn setup() -> Grammar {
+        Grammar::from_parts(
+            vec![
+                vec![257, 258, 100],
+                vec![97, 98, 99],
+                vec![100, 101, 259],
+                vec![102, 103, 104, 257],
+            ],
+            0,
+        )
+    }
+
+    fn encode(gr: &Grammar) -> String {
+        let mut buf = vec![];
+        GrammarTextCoder::encode(gr.clone(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn coding_decoding_test() {
+        let gr = setup();
+        let text = encode(&gr);
+        assert_eq!(
+            "alphabet 256\nstart R0\nR0 -> R1 R2 'd'\nR1 -> 'a' 'b' 'c'\nR2 -> 'd' 'e' R3\nR3 -> 'f' 'g' 'h' R1\n",
+            text,
+            "Grammar encoded incorrectly"
+        );
+        let decoded = GrammarTextCoder::decode(text.as_bytes());
+        assert_eq!(Some(gr), decoded.ok(), "Resulting grammar differs from original grammar");
+    }
+
+    #[test]
+    fn all_bytes_test() {
+        let text = (0..=255u8).chain(0..=255u8).collect::<Vec<_>>();
+        let gr = native_repair::repair_bytes(&text);
+        let decoded = GrammarTextCoder::decode(encode(&gr).as_bytes());
+        assert_eq!(Some(gr), decoded.ok(), "Grammar with all bytes not decoded correctly");
+    }
+
+    #[test]
+    fn integer_alphabet_test() {
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![70000, 3], vec![100000, 100000, 99999]], 1, 100000);
+        let decoded = GrammarTextCoder::decode(encode(&gr).as_bytes());
+        assert_eq!(Some(gr), decoded.ok(), "Grammar over integers not decoded correctly");
+    }
+
+    #[test]
+    fn empty_test() {
+        let gr = Grammar::empty();
+        let decoded = GrammarTextCoder::decode(encode(&gr).as_bytes());
+        assert_eq!(Some(gr), decoded.ok(), "Empty grammar not decoded correctly");
+    }
+
+    #[test]
+    fn hand_written_test() {
+        let text = "# Two greetings\n\
+            \n\
+            start R1\n\
+            R0 -> 'h' 'i' 33   # the terminal 33 is '!'\n\
+            R1 -> R0 ' ' R0 '\\n' '#' '\\x7f' '\\''\n";
+        let decoded = GrammarTextCoder::decode(text.as_bytes());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(
+            b"hi! hi!\n#\x7f'".to_vec(),
+            decoded.unwrap().bytes().collect::<Vec<_>>(),
+            "Hand-written grammar decoded incorrectly"
+        );
+    }
+
+    #[test]
+    fn parse_error_test() {
+        for (text, line) in [
+            ("R1 -> 'a'\n", 1),
+            ("R0 -> 'a'\nR0 -> 'b'\n", 2),
+            ("R0 'a'\n", 1),
+            ("R0 -> 'ab'\n", 1),
+            ("R0 -> 'a\n", 1),
+            ("R0 -> '\\q'\n", 1),
+            ("R0 -> 'é'\n", 1),
+            ("R0 -> 256\n", 1),
+            ("alphabet 10\n\nR0 -> 'a'\n", 3),
+            ("R0 -> 'a'\nalphabet 256\n", 2),
+            ("start\n", 1),
+            ("R0 -> 'a' start\n", 1),
+            ("R0 -> 99999999999999999999999\n", 1),
+            ("S0 -> 'a'\n", 1),
+            ("R0 -> ('a')\n", 1),
+        ] {
+            match GrammarTextCoder::decode(text.as_bytes()) {
+                Err(RReaderError::Parse { line: error_line, .. }) => {
+                    assert_eq!(line, error_line, "Wrong line reported for {text:?}")
+                }
+                other => panic!("Parsing {text:?} did not fail with a parse error: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_grammar_test() {
+        assert!(
+            matches!(
+                GrammarTextCoder::decode("R0 -> R0 'a'\n".as_bytes()),
+                Err(RReaderError::InvalidGrammar(GrammarError::Cycle(0)))
+            ),
+            "Cyclic grammar decoded"
+        );
+        assert!(
+            matches!(
+                GrammarTextCoder::decode("start R2\nR0 -> 'a'\n".as_bytes()),
+                Err(RReaderError::InvalidGrammar(GrammarError::StartRuleOutOfRange { .. }))
+            ),
+            "Grammar with missing start rule decoded"
+        );
+    }
+}
@@ -1,52 +1,80 @@
-use std::io::Read;
+use std::{io::Read, marker::PhantomData};
 
 use bitstream_io::{BitWrite, BitWriter, BitReader, BitRead, BigEndian};
 
-use crate::grammar::{self, Grammar};
+use crate::{
+    error::RReaderError,
+    grammar::{Grammar, RULE_OFFSET},
+};
 
-use super::grammar_coder::{GrammarDecoder, GrammarEncoder};
+use super::{
+    grammar_coder::{read_alphabet_size, read_version, write_alphabet_size, write_magic, CostModel, GrammarDecoder, GrammarEncoder},
+    number_coder::{bits_for, invalid, Fixed, NumberDecoder, NumberEncoder},
+};
 
+/// Writes each rule as its length followed by its symbols, each with a flag telling terminals and nonterminals apart.
+/// The rule lengths minus the minimum rule length are written with the `RuleLen` code and the ids of nonterminals with the `Symbol` code.
+/// [`GrammarTupleCoder`] writes both as 32-bit numbers.
+///
+/// The file starts with the magic bytes `RRGT` and the format version, followed by the rule count and the minimum and maximum
+/// rule length as 32-bit big-endian numbers and the alphabet size as a 64-bit big-endian number.
+/// Files written before the format had a version start with the rule count and have no alphabet size, which is 256 then.
+/// They are still decoded, as a file with the magic bytes would need more than a billion rules.
 #[derive(Default, Debug)]
-pub struct GrammarTupleCoder;
+pub struct GenericTupleCoder<RuleLen, Symbol>(PhantomData<(RuleLen, Symbol)>);
 
-// TODO create number encoders and make the grammar tuple coder take them
+/// The tuple coder with 32-bit rule lengths and rule ids
+pub type GrammarTupleCoder = GenericTupleCoder<Fixed<32>, Fixed<32>>;
 
-impl GrammarEncoder for GrammarTupleCoder {
+const MAGIC: &[u8; 4] = b"RRGT";
+const VERSION: u32 = 2;
+
+/// The most rules or symbols of a rule reserved before reading them.
+/// The counts in a header are not trusted with allocations before the data backing them was read,
+/// so a corrupt file cannot make a decoder reserve gigabytes up front.
+pub(super) const MAX_PREALLOCATION: usize = 1 << 16;
+
+impl<RuleLen: NumberEncoder, Symbol: NumberEncoder> GrammarEncoder for GenericTupleCoder<RuleLen, Symbol> {
     type EncodeErr = std::io::Error;
     fn encode<Out: std::io::Write>(mut grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
         let mut bit_writer = BitWriter::endian(out, BigEndian);
+        grammar.prune();
         grammar.renumber();
 
+        let alphabet_size = grammar.alphabet_size();
+        let terminal_bits = terminal_bits(alphabet_size);
         let (rules, _) = grammar.consume();
 
+        write_magic(&mut bit_writer, MAGIC, VERSION)?;
         // We write this to the output so we know when to stop reading, in case there are
         // additional padding bits
-        let rule_count = rules.len() as u32;
+        let rule_count = to_u32(rules.len(), "rule count")?;
         bit_writer.write_bytes(&u32::to_be_bytes(rule_count))?;
 
         let (min_len, max_len) = rules.iter()
-            .map(|v| v.len() as u32)
-            .fold((u32::MAX, 0), |(old_min, old_max), v| (u32::min(old_min, v), u32::max(old_max, v)));
+            .map(|v| v.len())
+            .fold((usize::MAX, 0), |(old_min, old_max), v| (usize::min(old_min, v), usize::max(old_max, v)));
+        let min_len = to_u32(min_len.min(max_len), "rule length")?;
+        let max_len = to_u32(max_len, "rule length")?;
 
         bit_writer.write_bytes(&u32::to_be_bytes(min_len))?;
         bit_writer.write_bytes(&u32::to_be_bytes(max_len))?;
 
+        write_alphabet_size(&mut bit_writer, alphabet_size)?;
+
         for rule in rules {
             // Write the rule length to the output
-            let encoded_rule_size = rule.len() as u32 - min_len;
-            bit_writer.write_bytes(&u32::to_be_bytes(encoded_rule_size))?;
+            RuleLen::encode_number((rule.len() - min_len as usize) as u64, &mut bit_writer)?;
 
             for symbol in rule {
-                if Grammar::is_terminal(symbol) {
+                if symbol < alphabet_size {
                     // If the symbol is a terminal we write a 0 bit and then the terminal itself
-                    let symbol = symbol as u8;
                     bit_writer.write_bit(false)?;
-                    bit_writer.write_bytes(&[symbol])?;
+                    bit_writer.write(terminal_bits, symbol as u64)?;
                 } else {
-                    // If the symbol is a non-terminal we write a 1 bit and then the symbol
-                    let symbol_bytes = ((symbol - grammar::RULE_OFFSET) as u32).to_be_bytes();
+                    // If the symbol is a non-terminal we write a 1 bit and then the rule id
                     bit_writer.write_bit(true)?;
-                    bit_writer.write_bytes(&symbol_bytes)?;
+                    Symbol::encode_number((symbol - alphabet_size) as u64, &mut bit_writer)?;
                 }
             }
         }
@@ -59,44 +87,61 @@ impl GrammarEncoder for GrammarTupleCoder {
     }
 }
 
-impl<I> GrammarDecoder<I> for GrammarTupleCoder
+impl<I, RuleLen, Symbol> GrammarDecoder<I> for GenericTupleCoder<RuleLen, Symbol>
 where
     I: Read,
+    RuleLen: NumberDecoder,
+    Symbol: NumberDecoder,
 {
-    type DecodeErr = std::io::Error;
+    type DecodeErr = RReaderError;
 
     fn decode(input: I) -> Result<Grammar, Self::DecodeErr> {
         let mut bit_reader = BitReader::endian(input, BigEndian);
     
         let mut buf32 = [0u8; 4];
-        let mut buf8 = [0u8];
 
         macro_rules! rd {
             (u32) => {{ 
                 bit_reader.read_bytes(&mut buf32)?;
                 u32::from_be_bytes(buf32) as usize
             }};
-            (u8) => {{ 
-                bit_reader.read_bytes(&mut buf8)?;
-                buf8[0] as usize
-            }};
         }
 
-        let rule_count = rd!(u32);
+        // Files without a version start with the rule count instead
+        let mut magic = [0u8; 4];
+        bit_reader.read_bytes(&mut magic)?;
+        let versioned = &magic == MAGIC;
+        if versioned {
+            read_version(&mut bit_reader, VERSION, "tuple")?;
+        }
+        let rule_count = if versioned { rd!(u32) } else { u32::from_be_bytes(magic) as usize };
         let min_len = rd!(u32);
         let _max_len = rd!(u32);
+        let alphabet_size = if versioned { read_alphabet_size(&mut bit_reader)? } else { RULE_OFFSET };
+        let terminal_bits = terminal_bits(alphabet_size);
 
-        let mut rules = Vec::with_capacity(rule_count); 
+        let mut rules = Vec::with_capacity(rule_count.min(MAX_PREALLOCATION));
 
         for _ in 0..rule_count {
-            let rule_size = rd!(u32) + min_len;
-            let mut rule = Vec::with_capacity(rule_size);
+            let rule_size = usize::try_from(RuleLen::decode_number(&mut bit_reader)?)
+                .ok()
+                .and_then(|len| len.checked_add(min_len))
+                .ok_or(RReaderError::TooLarge("rule length"))?;
+            let mut rule = Vec::with_capacity(rule_size.min(MAX_PREALLOCATION));
             for _ in 0..rule_size {
                 let is_nonterminal = bit_reader.read_bit()?;
                 let symbol = if is_nonterminal {
-                    rd!(u32) + grammar::RULE_OFFSET
+                    usize::try_from(Symbol::decode_number(&mut bit_reader)?)
+                        .ok()
+                        .and_then(|id| id.checked_add(alphabet_size))
+                        .ok_or(RReaderError::TooLarge("rule id"))?
                 } else {
-                    rd!(u8)
+                    let terminal = bit_reader.read::<u64>(terminal_bits)? as usize;
+                    // Unless the alphabet size is a power of two, the bits can hold more than the terminals
+                    if terminal >= alphabet_size {
+                        return Err(invalid("terminal outside the alphabet").into());
+                    }
+                    terminal
                 };
                 rule.push(symbol);
             } 
@@ -104,16 +149,58 @@ where
             rules.push(rule);
         }
 
-        Ok(Grammar::from_parts(rules, rule_count - 1))
+        let grammar = Grammar::from_parts_with_alphabet(rules, rule_count.saturating_sub(1), alphabet_size);
+        grammar.validate()?;
+        Ok(grammar)
     }
 }
 
+impl<RuleLen: NumberEncoder, Symbol: NumberEncoder> CostModel for GenericTupleCoder<RuleLen, Symbol> {
+    fn symbol_cost(grammar: &Grammar, symbol: usize) -> u64 {
+        // The flag bit, and the terminal or the rule id
+        match grammar.rule_index(symbol) {
+            None => 1 + terminal_bits(grammar.alphabet_size()) as u64,
+            Some(rule) => 1 + Symbol::number_cost(rule as u64),
+        }
+    }
+
+    fn rule_cost(_grammar: &Grammar) -> u64 {
+        // The rule length. Its offset from the minimum is not known here, so that of the shortest rule is assumed.
+        RuleLen::number_cost(0)
+    }
+}
+
+/// Converts a header field or rule id to the 32 bits the format stores it in
+pub(super) fn to_u32(value: usize, what: &str) -> std::io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{what} {value} does not fit into 32 bits"),
+        )
+    })
+}
+
+/// The number of bits needed to write any terminal of the alphabet, which is 8 for bytes
+fn terminal_bits(alphabet_size: usize) -> u32 {
+    bits_for(alphabet_size).max(1)
+}
+
 
 #[cfg(test)]
 mod test {
-    use crate::{grammar::Grammar, coding::grammar_coder::{GrammarEncoder, GrammarDecoder}};
+    use crate::{
+        coding::{
+            grammar_coder::{GrammarDecoder, GrammarEncoder},
+            number_coder::{Delta, Fixed, Gamma, Golomb, Leb128, NumberDecoder, NumberEncoder, Rice},
+            test_util::{encode, repetitive_text},
+        },
+        error::{GrammarError, RReaderError},
+        grammar::Grammar,
+        native_repair,
+    };
+    use bitstream_io::{BigEndian, BitWrite, BitWriter};
 
-    use super::GrammarTupleCoder;
+    use super::{GenericTupleCoder, GrammarTupleCoder, MAGIC, VERSION};
 
     fn setup() -> Grammar {
         Grammar::from_parts(
@@ -144,4 +231,230 @@ mod test {
         gr.renumber();
         assert_eq!(gr, decoded, "Resulting grammar differs from original grammar");
     }
+
+    #[test]
+    fn integer_alphabet_coding_decoding_test() {
+        let mut gr = Grammar::from_parts_with_alphabet(
+            vec![vec![70000, 3], vec![100000, 100000, 99999], vec![100001, 0, 100000]],
+            2,
+            100000,
+        );
+        let mut buf = vec![];
+
+        let encoded = GrammarTupleCoder::encode(gr.clone(), &mut buf);
+        assert!(encoded.is_ok(), "Error during encoding: {:?}", encoded);
+
+        let decoded = GrammarTupleCoder::decode(buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded);
+
+        gr.renumber();
+        assert_eq!(gr, decoded.unwrap(), "Resulting grammar differs from original grammar");
+    }
+
+    #[test]
+    fn code_point_coding_decoding_test() {
+        let text = "ünïcödé ☃ ünïcödé ☃ 𝄞𝄞𝄞 ünïcödé";
+        let code_points = text.chars().map(u32::from).collect::<Vec<_>>();
+        let gr = native_repair::repair_ints(&code_points);
+        let mut buf = vec![];
+
+        let encoded = GrammarTupleCoder::encode(gr, &mut buf);
+        assert!(encoded.is_ok(), "Error during encoding: {:?}", encoded);
+
+        let decoded = GrammarTupleCoder::decode(buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded);
+
+        let decoded = decoded
+            .unwrap()
+            .produce_source_symbols()
+            .into_iter()
+            .map(|symbol| char::from_u32(symbol as u32).unwrap())
+            .collect::<String>();
+        assert_eq!(text, decoded, "Code points not correctly reproduced");
+    }
+
+    #[test]
+    fn empty_coding_decoding_test() {
+        let mut buf = vec![];
+        let encoded = GrammarTupleCoder::encode(Grammar::empty(), &mut buf);
+        assert!(encoded.is_ok(), "Error during encoding: {:?}", encoded);
+
+        let decoded = GrammarTupleCoder::decode(buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded);
+        assert_eq!(Grammar::empty(), decoded.unwrap(), "Empty grammar not decoded as empty");
+    }
+
+    #[test]
+    fn corrupt_decoding_test() {
+        let mut buf = vec![];
+        GrammarTupleCoder::encode(setup(), &mut buf).unwrap();
+
+        // One rule of length 2, which is a and a reference to the nonexistent rule 5
+        let mut corrupt = vec![];
+        let mut writer = BitWriter::endian(&mut corrupt, BigEndian);
+        writer.write_bytes(MAGIC).unwrap();
+        for header in [VERSION, 1, 2, 2] {
+            writer.write_bytes(&header.to_be_bytes()).unwrap();
+        }
+        writer.write_bytes(&256u64.to_be_bytes()).unwrap();
+        writer.write_bytes(&0u32.to_be_bytes()).unwrap();
+        writer.write_bit(false).unwrap();
+        writer.write(8, b'a' as u32).unwrap();
+        writer.write_bit(true).unwrap();
+        writer.write_bytes(&5u32.to_be_bytes()).unwrap();
+        writer.byte_align().unwrap();
+        assert!(
+            matches!(
+                GrammarTupleCoder::decode(corrupt.as_slice()),
+                Err(RReaderError::InvalidGrammar(GrammarError::DanglingReference { rule: 0, symbol: 261 }))
+            ),
+            "Dangling rule reference not rejected"
+        );
+
+        // Claim there are more rules than the file contains
+        let mut corrupt = buf.clone();
+        corrupt[8] = 0xFF;
+        assert!(
+            matches!(GrammarTupleCoder::decode(corrupt.as_slice()), Err(RReaderError::IO(_))),
+            "Truncated file not rejected"
+        );
+
+        let mut corrupt = buf;
+        corrupt[7] = VERSION as u8 + 1;
+        assert!(
+            matches!(
+                GrammarTupleCoder::decode(corrupt.as_slice()),
+                Err(RReaderError::UnsupportedFormat { version: Some(version), .. }) if version == VERSION + 1
+            ),
+            "Unknown format version not rejected"
+        );
+
+        // Two rules over 3 terminals, so terminals take 2 bits. The start rule's terminal 3 would be a reference to rule 0.
+        let mut corrupt = vec![];
+        let mut writer = BitWriter::endian(&mut corrupt, BigEndian);
+        writer.write_bytes(MAGIC).unwrap();
+        for header in [VERSION, 2, 1, 1] {
+            writer.write_bytes(&header.to_be_bytes()).unwrap();
+        }
+        writer.write_bytes(&3u64.to_be_bytes()).unwrap();
+        for terminal in [0u32, 3] {
+            writer.write_bytes(&0u32.to_be_bytes()).unwrap();
+            writer.write_bit(false).unwrap();
+            writer.write(2, terminal).unwrap();
+        }
+        writer.byte_align().unwrap();
+        assert!(
+            matches!(GrammarTupleCoder::decode(corrupt.as_slice()), Err(RReaderError::IO(_))),
+            "Terminal outside the alphabet not rejected"
+        );
+    }
+
+    #[test]
+    fn unversioned_decoding_test() {
+        // Files without a version lack the magic bytes, the version and the alphabet size, but are the same otherwise
+        let gr = setup();
+        let mut buf = vec![];
+        GrammarTupleCoder::encode(gr.clone(), &mut buf).unwrap();
+        assert_eq!(MAGIC, &buf[..4], "Magic bytes missing");
+        let unversioned = [&buf[8..20], &buf[28..]].concat();
+
+        let decoded = GrammarTupleCoder::decode(unversioned.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(gr.produce_source_string(), decoded.unwrap().produce_source_string(), "Unversioned file decoded incorrectly");
+    }
+
+    #[test]
+    fn unreachable_coding_decoding_test() {
+        // Rule 0 is unreachable from rule 2
+        let mut gr = setup();
+        gr.set_start_rule(2);
+        let mut buf = vec![];
+
+        let encoded = GrammarTupleCoder::encode(gr.clone(), &mut buf);
+        assert!(encoded.is_ok(), "Error during encoding: {:?}", encoded);
+
+        let decoded = GrammarTupleCoder::decode(buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded);
+
+        let decoded = decoded.unwrap();
+        assert_eq!(3, decoded.rule_count(), "Unreachable rule encoded");
+        assert_eq!(gr.produce_source_string(), decoded.produce_source_string(), "Decoded grammar producing the wrong string");
+    }
+
+    #[test]
+    fn inlined_coding_decoding_test() {
+        let text = (0..20000usize).map(|i| b'a' + (i % 13 + i % 7) as u8).collect::<Vec<_>>();
+        let gr = native_repair::repair_bytes(&text);
+        let mut inlined = gr.clone();
+        inlined.inline::<GrammarTupleCoder>(usize::MAX);
+
+        let mut buf = vec![];
+        let mut inlined_buf = vec![];
+        GrammarTupleCoder::encode(gr, &mut buf).unwrap();
+        GrammarTupleCoder::encode(inlined, &mut inlined_buf).unwrap();
+        assert!(
+            inlined_buf.len() < buf.len(),
+            "Inlining did not shrink the encoding: {} >= {} bytes",
+            inlined_buf.len(),
+            buf.len()
+        );
+
+        let decoded = GrammarTupleCoder::decode(inlined_buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded);
+        assert_eq!(text, decoded.unwrap().bytes().collect::<Vec<_>>(), "Inlined grammar producing the wrong text");
+    }
+
+    /// Encodes the grammar with the given number codes, checks that it decodes to the text and returns its size
+    fn encoded_len<RuleLen, Symbol>(gr: &Grammar, text: &[u8]) -> usize
+    where
+        RuleLen: NumberEncoder + NumberDecoder,
+        Symbol: NumberEncoder + NumberDecoder,
+    {
+        let mut buf = vec![];
+        GenericTupleCoder::<RuleLen, Symbol>::encode(gr.clone(), &mut buf).unwrap();
+        let decoded = GenericTupleCoder::<RuleLen, Symbol>::decode(buf.as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(text, decoded.unwrap().bytes().collect::<Vec<_>>(), "Decoded grammar producing the wrong text");
+        buf.len()
+    }
+
+    #[test]
+    fn number_coders_test() {
+        let text = repetitive_text();
+        let gr = native_repair::repair_bytes(&text);
+
+        let default_len = encoded_len::<Fixed<32>, Fixed<32>>(&gr, &text);
+        let mut buf = vec![];
+        GrammarTupleCoder::encode(gr.clone(), &mut buf).unwrap();
+        assert_eq!(buf.len(), default_len, "Default number codes not 32 bits wide");
+
+        for len in [
+            encoded_len::<Gamma, Gamma>(&gr, &text),
+            encoded_len::<Delta, Delta>(&gr, &text),
+            encoded_len::<Rice<2>, Leb128>(&gr, &text),
+            encoded_len::<Golomb<3>, Fixed<16>>(&gr, &text),
+        ] {
+            assert!(len < default_len, "Variable-length codes not smaller: {len} >= {default_len} bytes");
+        }
+
+        let mut buf = vec![];
+        let result = GenericTupleCoder::<Fixed<32>, Fixed<4>>::encode(gr, &mut buf);
+        assert!(result.is_err(), "Rule ids encoded with too few bits");
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn full_integer_alphabet_test() {
+        // The largest integer makes the alphabet one larger than 32 bits can hold
+        let symbols = [u32::MAX, 0, u32::MAX, 0];
+        let gr = native_repair::repair_ints(&symbols);
+        assert_eq!(1 << 32, gr.alphabet_size(), "Alphabet not covering the largest integer");
+        let decoded = GrammarTupleCoder::decode(encode::<GrammarTupleCoder>(&gr).as_slice());
+        assert!(decoded.is_ok(), "Error during decoding: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Grammar over the full range of integers decoded incorrectly"
+        );
+    }
 }
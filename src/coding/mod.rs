@@ -1,3 +1,128 @@
+pub mod grammar_ans_coder;
 pub mod grammar_coder;
+pub mod grammar_huffman_coder;
+pub mod grammar_packed_coder;
+pub mod grammar_text_coder;
 pub mod grammar_tuple_coder;
 pub mod navarro_repair_decoder;
+pub mod navarro_repair_encoder;
+pub mod number_coder;
+#[cfg(test)]
+mod test_util;
+
+use std::{fmt::Display, io::Read, io::Write, str::FromStr};
+
+use crate::{error::RReaderError, grammar::Grammar};
+
+use grammar_coder::{GrammarDecoder, GrammarEncoder};
+use grammar_ans_coder::GrammarAnsCoder;
+use grammar_huffman_coder::GrammarHuffmanCoder;
+use grammar_packed_coder::GrammarPackedCoder;
+use grammar_text_coder::GrammarTextCoder;
+use grammar_tuple_coder::GrammarTupleCoder;
+
+/// The file formats grammars can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The binary format of [`GrammarTupleCoder`]
+    #[default]
+    Tuple,
+    /// The bit-packed format of [`GrammarPackedCoder`]
+    Packed,
+    /// The Huffman-coded format of [`GrammarHuffmanCoder`]
+    Huffman,
+    /// The rANS-coded format of [`GrammarAnsCoder`]
+    Ans,
+    /// The human-readable format of [`GrammarTextCoder`]
+    Text,
+}
+
+impl Format {
+    pub fn encode(self, grammar: Grammar, out: impl Write) -> Result<(), RReaderError> {
+        match self {
+            Format::Tuple => GrammarTupleCoder::encode(grammar, out)?,
+            Format::Packed => GrammarPackedCoder::encode(grammar, out)?,
+            Format::Huffman => GrammarHuffmanCoder::encode(grammar, out)?,
+            Format::Ans => GrammarAnsCoder::encode(grammar, out)?,
+            Format::Text => GrammarTextCoder::encode(grammar, out)?,
+        }
+        Ok(())
+    }
+
+    /// Inlines the rules that are cheaper to repeat in this format, see [`Grammar::inline`]
+    pub fn inline(self, grammar: &mut Grammar, max_len: usize) -> usize {
+        match self {
+            // Entropy codes are not known before inlining, so they are approximated by fixed-width symbols
+            Format::Packed | Format::Huffman | Format::Ans => grammar.inline::<GrammarPackedCoder>(max_len),
+            // The text format is meant to be read, not to be small, so it is inlined like the default
+            Format::Tuple | Format::Text => grammar.inline::<GrammarTupleCoder>(max_len),
+        }
+    }
+
+    pub fn decode(self, input: impl Read) -> Result<Grammar, RReaderError> {
+        match self {
+            Format::Tuple => GrammarTupleCoder::decode(input),
+            Format::Packed => GrammarPackedCoder::decode(input),
+            Format::Huffman => GrammarHuffmanCoder::decode(input),
+            Format::Ans => GrammarAnsCoder::decode(input),
+            Format::Text => GrammarTextCoder::decode(input),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tuple" => Ok(Format::Tuple),
+            "packed" => Ok(Format::Packed),
+            "huffman" => Ok(Format::Huffman),
+            "ans" => Ok(Format::Ans),
+            "text" => Ok(Format::Text),
+            _ => Err(format!("unknown grammar format {s}")),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Tuple => write!(f, "tuple"),
+            Format::Packed => write!(f, "packed"),
+            Format::Huffman => write!(f, "huffman"),
+            Format::Ans => write!(f, "ans"),
+            Format::Text => write!(f, "text"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::Grammar;
+
+    use super::Format;
+
+    #[test]
+    fn format_parse_test() {
+        for format in [Format::Tuple, Format::Packed, Format::Huffman, Format::Ans, Format::Text] {
+            assert_eq!(Ok(format), format.to_string().parse(), "Format {format} not parsed");
+        }
+        assert!("zip".parse::<Format>().is_err(), "Unknown format parsed");
+    }
+
+    #[test]
+    fn format_coding_decoding_test() {
+        let gr = Grammar::from_parts(vec![vec![257, 257, 33], vec![104, 105]], 0);
+        for format in [Format::Tuple, Format::Packed, Format::Huffman, Format::Ans, Format::Text] {
+            let mut buf = vec![];
+            format.encode(gr.clone(), &mut buf).unwrap();
+            let decoded = format.decode(buf.as_slice()).unwrap();
+            assert_eq!(
+                Ok("hihi!".to_owned()),
+                decoded.produce_source_string(),
+                "Grammar in format {format} decoded incorrectly"
+            );
+        }
+    }
+}
@@ -1,93 +1,220 @@
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
 use crate::{
     error::RReaderError,
     grammar::{Grammar, RULE_OFFSET},
 };
 
-use super::grammar_coder::GrammarDecoder;
-
-macro_rules! read_int {
-    ($chars:ident) => {{
-        let a = $chars
-            .next()
-            .ok_or(RReaderError::MissingInput("Alphabet size"))?;
-        let b = $chars
-            .next()
-            .ok_or(RReaderError::MissingInput("Alphabet size"))?;
-        let c = $chars
-            .next()
-            .ok_or(RReaderError::MissingInput("Alphabet size"))?;
-        let d = $chars
-            .next()
-            .ok_or(RReaderError::MissingInput("Alphabet size"))?;
-        u32::from_le_bytes([a, b, c, d]) as usize
-    }};
-}
+use super::{grammar_coder::GrammarDecoder, grammar_tuple_coder::MAX_PREALLOCATION};
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct NavarroRepairDecoder;
 
+#[derive(Debug, Default, Clone)]
 pub struct RePairResult {
     pub file_c: Vec<u8>,
-    pub file_r: Vec<u8>
+    pub file_r: Vec<u8>,
+    pub int_width: IntWidth,
+}
+
+impl RePairResult {
+    /// Reads `<base>.C` and `<base>.R`, the files Navarro's RePair writes next to its input file `<base>`
+    pub fn read_files(base: impl AsRef<Path>, int_width: IntWidth) -> io::Result<Self> {
+        let base = base.as_ref();
+        Ok(Self {
+            file_c: fs::read(with_suffix(base, ".C"))?,
+            file_r: fs::read(with_suffix(base, ".R"))?,
+            int_width,
+        })
+    }
+
+    /// Writes `<base>.C` and `<base>.R`, replacing existing files
+    pub fn write_files(&self, base: impl AsRef<Path>) -> io::Result<()> {
+        let base = base.as_ref();
+        fs::write(with_suffix(base, ".C"), &self.file_c)?;
+        fs::write(with_suffix(base, ".R"), &self.file_r)
+    }
+
+    /// Writes both files into a single stream: the length of the `.R` file as a 64-bit little-endian integer,
+    /// the `.R` file and then the `.C` file. This is what the `GrammarEncoder` impls of the Navarro encoders write.
+    pub fn write_bundle(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(&(self.file_r.len() as u64).to_le_bytes())?;
+        out.write_all(&self.file_r)?;
+        out.write_all(&self.file_c)?;
+        out.flush()
+    }
+
+    /// Reads both files from a stream written by [`RePairResult::write_bundle`]
+    pub fn read_bundle(mut input: impl Read, int_width: IntWidth) -> io::Result<Self> {
+        let mut len = [0u8; 8];
+        input.read_exact(&mut len)?;
+        let mut file_r = vec![];
+        input.by_ref().take(u64::from_le_bytes(len)).read_to_end(&mut file_r)?;
+        if file_r.len() as u64 != u64::from_le_bytes(len) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bundle ends within the .R file"));
+        }
+        let mut file_c = vec![];
+        input.read_to_end(&mut file_c)?;
+        Ok(Self { file_c, file_r, int_width })
+    }
+}
+
+/// Appends the suffix to the path, keeping any extension it already has
+fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(base);
+    path.push(suffix);
+    path.into()
+}
+
+/// The width of the little-endian integers in the `.R` and `.C` files
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IntWidth {
+    /// 32-bit integers, as written by Navarro's original RePair
+    #[default]
+    U32,
+    /// 64-bit integers, as written by the bundled RePair so inputs over 2^31 symbols fit
+    U64,
+}
+
+impl IntWidth {
+    /// The number of bytes of each integer
+    pub fn bytes(self) -> usize {
+        match self {
+            IntWidth::U32 => 4,
+            IntWidth::U64 => 8,
+        }
+    }
 }
 
 impl GrammarDecoder<RePairResult> for NavarroRepairDecoder {
     type DecodeErr = RReaderError;
 
     fn decode(res: RePairResult) -> Result<Grammar, Self::DecodeErr> {
-        let file_r = res.file_r;
-        let file_c = res.file_c;
+        let width = res.int_width;
+        let mut chars = res.file_r.into_iter().peekable();
 
-        let mut chars = file_r.into_iter().peekable();
-
-        // Read alphabet size
-        let alph_n = read_int!(chars);
+        // Read alphabet size, which counts distinct bytes
+        let alph_n = read_int(&mut chars, width, "Alphabet size")?;
+        if alph_n > 256 {
+            return Err(RReaderError::TooLarge("Alphabet size"));
+        }
         // Read the alphabet
         let alph = alphabet(alph_n, &mut chars)?;
 
+        // Ids below the alphabet size index the alphabet, and the others are rules. Both come from the file, so nothing may overflow.
+        let symbol = |id: usize, what| {
+            if id < alph_n {
+                Ok(alph[id] as usize)
+            } else {
+                (id - alph_n).checked_add(RULE_OFFSET).ok_or(RReaderError::TooLarge(what))
+            }
+        };
+
         // The rule vector
         let mut rules = vec![];
 
         while chars.peek().is_some() {
-            let mut l = read_int!(chars);
-            let mut r = read_int!(chars);
+            let l = symbol(read_int(&mut chars, width, "Rule")?, "Rule")?;
+            let r = symbol(read_int(&mut chars, width, "Rule")?, "Rule")?;
+            rules.push(vec![l, r]);
+        }
 
-            l = if l < alph_n {
-                alph[l] as usize
-            } else {
-                l - alph_n + RULE_OFFSET
-            };
+        let mut chars = res.file_c.into_iter().peekable();
+        let mut rule_vec = vec![];
+        while chars.peek().is_some() {
+            rule_vec.push(symbol(read_int(&mut chars, width, "Symbol")?, "Symbol")?);
+        }
+        rules.push(rule_vec);
 
-            r = if r < alph_n {
-                alph[r] as usize
-            } else {
-                r - alph_n + RULE_OFFSET
-            };
+        let n_rules = rules.len();
+        let grammar = Grammar::from_parts(rules, n_rules - 1);
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
+
+impl GrammarDecoder<&Path> for NavarroRepairDecoder {
+    type DecodeErr = RReaderError;
+
+    /// Decodes `<base>.C` and `<base>.R` with 32-bit integers, as written by Navarro's RePair
+    fn decode(base: &Path) -> Result<Grammar, Self::DecodeErr> {
+        Self::decode(RePairResult::read_files(base, IntWidth::U32)?)
+    }
+}
+
+/// Decodes the output of RePair on a sequence of integers, like that of Navarro's `irepair`.
+/// There is no alphabet map in the `.R` file, since the terminals are the integers themselves.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NavarroIntRepairDecoder;
+
+impl GrammarDecoder<RePairResult> for NavarroIntRepairDecoder {
+    type DecodeErr = RReaderError;
+
+    fn decode(res: RePairResult) -> Result<Grammar, Self::DecodeErr> {
+        let width = res.int_width;
+        let max_rules = res.file_r.len() / (2 * width.bytes()) + 1;
+        let mut chars = res.file_r.into_iter().peekable();
+
+        // Terminals are 0..alph_n, and rule i is alph_n + i, just like in the grammar
+        let alph_n = read_int(&mut chars, width, "Alphabet size")?;
+        // The symbols of all rules the file can hold have to be representable
+        if alph_n.checked_add(max_rules).is_none() {
+            return Err(RReaderError::TooLarge("Alphabet size"));
+        }
+
+        let mut rules = vec![];
+        while chars.peek().is_some() {
+            let l = read_int(&mut chars, width, "Rule")?;
+            let r = read_int(&mut chars, width, "Rule")?;
             rules.push(vec![l, r]);
         }
 
-        let mut chars = file_c.into_iter().peekable();
+        let mut chars = res.file_c.into_iter().peekable();
         let mut rule_vec = vec![];
         while chars.peek().is_some() {
-            let symb = read_int!(chars);
-            rule_vec.push(if symb < alph_n {
-                alph[symb] as usize
-            } else {
-                symb - alph_n + RULE_OFFSET
-            });
+            rule_vec.push(read_int(&mut chars, width, "Symbol")?);
         }
         rules.push(rule_vec);
 
         let n_rules = rules.len();
-        Ok(Grammar::from_parts(rules, n_rules - 1))
+        let grammar = Grammar::from_parts_with_alphabet(rules, n_rules - 1, alph_n);
+        grammar.validate()?;
+        Ok(grammar)
     }
 }
 
+impl GrammarDecoder<&Path> for NavarroIntRepairDecoder {
+    type DecodeErr = RReaderError;
+
+    /// Decodes `<base>.C` and `<base>.R` with 32-bit integers, as written by Navarro's `irepair`
+    fn decode(base: &Path) -> Result<Grammar, Self::DecodeErr> {
+        Self::decode(RePairResult::read_files(base, IntWidth::U32)?)
+    }
+}
+
+/// Reads a little-endian integer of the given width, failing if it does not fit into a usize
+fn read_int(
+    chars: &mut impl Iterator<Item = u8>,
+    width: IntWidth,
+    what: &'static str,
+) -> Result<usize, RReaderError> {
+    let mut buf = [0u8; 8];
+    for byte in buf.iter_mut().take(width.bytes()) {
+        *byte = chars.next().ok_or(RReaderError::MissingInput(what))?;
+    }
+    usize::try_from(u64::from_le_bytes(buf)).map_err(|_| RReaderError::TooLarge(what))
+}
+
 fn alphabet(
     alph_n: usize,
     chars: &mut impl Iterator<Item = u8>,
 ) -> Result<Vec<char>, RReaderError> {
-    let mut vec = Vec::with_capacity(alph_n);
+    let mut vec = Vec::with_capacity(alph_n.min(MAX_PREALLOCATION));
     for _ in 0..alph_n {
         vec.push(
             chars
@@ -103,34 +230,42 @@ fn alphabet(
 mod test {
     use std::io::Write;
 
-    use crate::{coding::grammar_coder::GrammarDecoder, grammar::Grammar};
+    use crate::{
+        coding::grammar_coder::GrammarDecoder,
+        error::{GrammarError, RReaderError},
+        grammar::Grammar,
+    };
 
-    use super::NavarroRepairDecoder;
+    use super::{IntWidth, NavarroIntRepairDecoder, NavarroRepairDecoder, RePairResult};
 
 
     #[test]
     fn navarro_decode_test() {
         // alphabet: a c e g
         let mut r_bytes = vec![4u8, 0, 0, 0, 97, 99, 101, 103];
-        r_bytes.write(&[0, 0, 0, 0]).unwrap(); // a
-        r_bytes.write(&[1, 0, 0, 0]).unwrap(); // c
+        r_bytes.write_all(&[0, 0, 0, 0]).unwrap(); // a
+        r_bytes.write_all(&[1, 0, 0, 0]).unwrap(); // c
         
-        r_bytes.write(&[4, 0, 0, 0]).unwrap(); // ac 
-        r_bytes.write(&[2, 0, 0, 0]).unwrap(); // e
+        r_bytes.write_all(&[4, 0, 0, 0]).unwrap(); // ac 
+        r_bytes.write_all(&[2, 0, 0, 0]).unwrap(); // e
         
-        r_bytes.write(&[3, 0, 0, 0]).unwrap(); // g
-        r_bytes.write(&[0, 0, 0, 0]).unwrap(); // a
+        r_bytes.write_all(&[3, 0, 0, 0]).unwrap(); // g
+        r_bytes.write_all(&[0, 0, 0, 0]).unwrap(); // a
         
-        r_bytes.write(&[5, 0, 0, 0]).unwrap(); // ace
-        r_bytes.write(&[6, 0, 0, 0]).unwrap(); // ga
+        r_bytes.write_all(&[5, 0, 0, 0]).unwrap(); // ace
+        r_bytes.write_all(&[6, 0, 0, 0]).unwrap(); // ga
         
         let mut c_bytes: Vec<u8> = vec![];
-        c_bytes.write(&[5, 0, 0, 0]).unwrap(); // ace
-        c_bytes.write(&[7, 0, 0, 0]).unwrap(); // acega
-        c_bytes.write(&[1, 0, 0, 0]).unwrap(); // c
-        c_bytes.write(&[6, 0, 0, 0]).unwrap(); // ga
+        c_bytes.write_all(&[5, 0, 0, 0]).unwrap(); // ace
+        c_bytes.write_all(&[7, 0, 0, 0]).unwrap(); // acega
+        c_bytes.write_all(&[1, 0, 0, 0]).unwrap(); // c
+        c_bytes.write_all(&[6, 0, 0, 0]).unwrap(); // ga
         
-        let gr = NavarroRepairDecoder::decode(super::RePairResult { file_c: c_bytes, file_r: r_bytes });
+        let gr = NavarroRepairDecoder::decode(RePairResult {
+            file_c: c_bytes,
+            file_r: r_bytes,
+            int_width: IntWidth::U32,
+        });
         assert!(gr.is_ok(), "Error decoding grammar");
         let gr = gr.unwrap();
 
@@ -145,4 +280,185 @@ mod test {
         let s = gr.produce_source_string();
         assert_eq!(Ok("aceacegacga".to_owned()), s, "Grammar producing the wrong string");
     }
+
+    #[test]
+    fn navarro_int_decode_test() {
+        // terminals 0..1000
+        let mut r_bytes = vec![];
+        r_bytes.write_all(&1000u32.to_le_bytes()).unwrap();
+        r_bytes.write_all(&7u32.to_le_bytes()).unwrap();
+        r_bytes.write_all(&999u32.to_le_bytes()).unwrap(); // 1000 -> 7 999
+        r_bytes.write_all(&1000u32.to_le_bytes()).unwrap();
+        r_bytes.write_all(&1000u32.to_le_bytes()).unwrap(); // 1001 -> 1000 1000
+
+        let mut c_bytes: Vec<u8> = vec![];
+        c_bytes.write_all(&1001u32.to_le_bytes()).unwrap();
+        c_bytes.write_all(&3u32.to_le_bytes()).unwrap();
+        c_bytes.write_all(&1000u32.to_le_bytes()).unwrap();
+
+        let gr = NavarroIntRepairDecoder::decode(RePairResult {
+            file_c: c_bytes,
+            file_r: r_bytes,
+            int_width: IntWidth::U32,
+        });
+        assert!(gr.is_ok(), "Error decoding grammar");
+        let gr = gr.unwrap();
+
+        assert_eq!(
+            &Grammar::from_parts_with_alphabet(
+                vec![vec![7, 999], vec![1000, 1000], vec![1001, 3, 1000]],
+                2,
+                1000
+            ),
+            &gr,
+            "Grammar decoded incorrectly"
+        );
+        assert_eq!(
+            vec![7, 999, 7, 999, 3, 7, 999],
+            gr.produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+    }
+
+    #[test]
+    fn navarro_wide_decode_test() {
+        // Rule 1 of 2^32 + 1 terminals does not fit into 32 bits
+        let alph_n = (1u64 << 32) + 1;
+        let mut r_bytes = vec![];
+        r_bytes.write_all(&alph_n.to_le_bytes()).unwrap();
+        r_bytes.write_all(&(alph_n - 1).to_le_bytes()).unwrap();
+        r_bytes.write_all(&5u64.to_le_bytes()).unwrap(); // alph_n -> alph_n - 1, 5
+        r_bytes.write_all(&alph_n.to_le_bytes()).unwrap();
+        r_bytes.write_all(&alph_n.to_le_bytes()).unwrap(); // alph_n + 1 -> alph_n, alph_n
+
+        let mut c_bytes: Vec<u8> = vec![];
+        c_bytes.write_all(&(alph_n + 1).to_le_bytes()).unwrap();
+        c_bytes.write_all(&0u64.to_le_bytes()).unwrap();
+
+        let res = RePairResult {
+            file_c: c_bytes,
+            file_r: r_bytes,
+            int_width: IntWidth::U64,
+        };
+
+        #[cfg(target_pointer_width = "64")]
+        {
+            let alph_n = alph_n as usize;
+            let gr = NavarroIntRepairDecoder::decode(res);
+            assert!(gr.is_ok(), "Error decoding grammar: {:?}", gr.err());
+            assert_eq!(
+                Grammar::from_parts_with_alphabet(
+                    vec![vec![alph_n - 1, 5], vec![alph_n, alph_n], vec![alph_n + 1, 0]],
+                    2,
+                    alph_n
+                ),
+                gr.unwrap(),
+                "Grammar decoded incorrectly"
+            );
+        }
+        #[cfg(not(target_pointer_width = "64"))]
+        assert!(
+            matches!(NavarroIntRepairDecoder::decode(res), Err(RReaderError::TooLarge(_))),
+            "Integer not fitting into usize decoded"
+        );
+    }
+
+    #[test]
+    fn navarro_truncated_decode_test() {
+        let res = RePairResult {
+            file_c: vec![1, 0, 0, 0],
+            file_r: vec![0, 0, 0, 0, 0, 0, 0, 0],
+            int_width: IntWidth::U64,
+        };
+        assert!(
+            matches!(NavarroIntRepairDecoder::decode(res), Err(RReaderError::MissingInput("Symbol"))),
+            "Truncated symbol decoded"
+        );
+    }
+
+    #[test]
+    fn navarro_corrupt_alphabet_test() {
+        // An alphabet of 2^32 - 1 bytes, with only a single byte following
+        let res = RePairResult {
+            file_c: vec![],
+            file_r: vec![0xFF, 0xFF, 0xFF, 0xFF, b'a'],
+            int_width: IntWidth::U32,
+        };
+        assert!(
+            matches!(NavarroRepairDecoder::decode(res), Err(RReaderError::TooLarge("Alphabet size"))),
+            "Alphabet of more than 256 bytes decoded"
+        );
+
+        // An alphabet of 256 bytes, of which the file has one
+        let res = RePairResult {
+            file_c: vec![],
+            file_r: vec![0, 1, 0, 0, b'a'],
+            int_width: IntWidth::U32,
+        };
+        assert!(
+            matches!(NavarroRepairDecoder::decode(res), Err(RReaderError::MissingInput("Alphabet character"))),
+            "Truncated alphabet decoded"
+        );
+
+        // No rule of a file this short can be numbered after an alphabet of 2^64 - 1 integers
+        let res = RePairResult {
+            file_c: vec![],
+            file_r: u64::MAX.to_le_bytes().to_vec(),
+            int_width: IntWidth::U64,
+        };
+        assert!(
+            matches!(NavarroIntRepairDecoder::decode(res), Err(RReaderError::TooLarge(_))),
+            "Alphabet leaving no room for the rules decoded"
+        );
+    }
+
+    #[test]
+    fn navarro_corrupt_rule_test() {
+        // alphabet: a, and the only rule refers to a rule id so large that it cannot be numbered after the bytes
+        let mut r_bytes = 1u64.to_le_bytes().to_vec();
+        r_bytes.push(b'a');
+        r_bytes.extend([0, u64::MAX].iter().flat_map(|i| i.to_le_bytes()));
+        let res = RePairResult {
+            file_c: 1u64.to_le_bytes().to_vec(),
+            file_r: r_bytes,
+            int_width: IntWidth::U64,
+        };
+        assert!(
+            matches!(NavarroRepairDecoder::decode(res.clone()), Err(RReaderError::TooLarge("Rule"))),
+            "Rule id overflowing decoded"
+        );
+
+        let res = RePairResult {
+            file_c: u64::MAX.to_le_bytes().to_vec(),
+            file_r: res.file_r[..9].to_vec(),
+            int_width: IntWidth::U64,
+        };
+        assert!(
+            matches!(NavarroRepairDecoder::decode(res), Err(RReaderError::TooLarge("Symbol"))),
+            "Symbol overflowing decoded"
+        );
+    }
+
+    #[test]
+    fn navarro_dangling_decode_test() {
+        // alphabet: a, and the only rule refers to the nonexistent rule 2
+        let r_bytes = [1u32, 0, 3].iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let c_bytes = [1u32, 1].iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut r_bytes_with_map = r_bytes[..4].to_vec();
+        r_bytes_with_map.push(b'a');
+        r_bytes_with_map.extend_from_slice(&r_bytes[4..]);
+
+        let res = NavarroRepairDecoder::decode(RePairResult {
+            file_c: c_bytes,
+            file_r: r_bytes_with_map,
+            int_width: IntWidth::U32,
+        });
+        assert!(
+            matches!(
+                res,
+                Err(RReaderError::InvalidGrammar(GrammarError::DanglingReference { rule: 0, symbol: 258 }))
+            ),
+            "Dangling rule reference not rejected"
+        );
+    }
 }
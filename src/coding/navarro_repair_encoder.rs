@@ -0,0 +1,276 @@
+use std::io::Write;
+
+use crate::{error::RReaderError, grammar::Grammar};
+
+use super::{grammar_coder::GrammarEncoder, navarro_repair_decoder::RePairResult};
+
+/// Writes a byte grammar in the `.R` and `.C` format of Navarro's RePair, so it can be read by his `despair`.
+/// Rules with more than two symbols, apart from the start rule, are split into pairs first.
+///
+/// The `.R` file holds the number of distinct bytes, the sorted bytes themselves and the pairs.
+/// The `.C` file holds the start rule. All integers are 32-bit little-endian, as in the original.
+/// As a [`GrammarEncoder`], both files are written into one stream, see [`RePairResult::write_bundle`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NavarroRepairEncoder;
+
+/// Writes a grammar in the format of Navarro's `irepair`, which has no map of the terminals in the `.R` file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NavarroIntRepairEncoder;
+
+impl NavarroRepairEncoder {
+    /// Writes the contents of the grammar's `.C` file to `c` and those of its `.R` file to `r`
+    pub fn encode_to_files(grammar: Grammar, c: impl Write, mut r: impl Write) -> Result<(), RReaderError> {
+        if grammar.alphabet_size() > 256 {
+            return Err(RReaderError::TooLarge("Alphabet size"));
+        }
+        let grammar = pair_rules(grammar)?;
+
+        // Only the bytes occurring in the grammar are part of the alphabet, numbered in ascending order
+        let mut used = [false; 256];
+        for &symbol in grammar.rules().iter().flatten() {
+            if grammar.is_terminal_symbol(symbol) {
+                used[symbol] = true;
+            }
+        }
+        let map = (0..=255u8).filter(|&byte| used[byte as usize]).collect::<Vec<_>>();
+        let mut index = [0; 256];
+        for (i, &byte) in map.iter().enumerate() {
+            index[byte as usize] = i;
+        }
+
+        write_int(&mut r, map.len(), "Alphabet size")?;
+        r.write_all(&map)?;
+        write_rules(&grammar, c, r, |symbol| match grammar.rule_index(symbol) {
+            Some(rule) => map.len() + rule,
+            None => index[symbol],
+        })
+    }
+}
+
+impl NavarroIntRepairEncoder {
+    /// Writes the contents of the grammar's `.C` file to `c` and those of its `.R` file to `r`
+    pub fn encode_to_files(grammar: Grammar, c: impl Write, mut r: impl Write) -> Result<(), RReaderError> {
+        let grammar = pair_rules(grammar)?;
+
+        // Terminals are 0..alphabet_size, and rule i is alphabet_size + i, just like in the grammar
+        write_int(&mut r, grammar.alphabet_size(), "Alphabet size")?;
+        write_rules(&grammar, c, r, |symbol| symbol)
+    }
+}
+
+impl GrammarEncoder for NavarroRepairEncoder {
+    type EncodeErr = RReaderError;
+
+    fn encode<Out: Write>(grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
+        let mut res = RePairResult::default();
+        Self::encode_to_files(grammar, &mut res.file_c, &mut res.file_r)?;
+        Ok(res.write_bundle(out)?)
+    }
+}
+
+impl GrammarEncoder for NavarroIntRepairEncoder {
+    type EncodeErr = RReaderError;
+
+    fn encode<Out: Write>(grammar: Grammar, out: Out) -> Result<(), Self::EncodeErr> {
+        let mut res = RePairResult::default();
+        Self::encode_to_files(grammar, &mut res.file_c, &mut res.file_r)?;
+        Ok(res.write_bundle(out)?)
+    }
+}
+
+/// Converts the grammar into pairs with the start rule last, which is where the decoder expects the `.C` file
+fn pair_rules(grammar: Grammar) -> Result<Grammar, RReaderError> {
+    let mut grammar = grammar.to_pair_rules()?;
+    grammar.renumber();
+    Ok(grammar)
+}
+
+/// Writes all rules but the start rule to the `.R` file and the start rule to the `.C` file, mapping each symbol first
+fn write_rules(
+    grammar: &Grammar,
+    mut c: impl Write,
+    mut r: impl Write,
+    symbol: impl Fn(usize) -> usize,
+) -> Result<(), RReaderError> {
+    if let Some((start, pairs)) = grammar.rules().split_last() {
+        for &s in pairs.iter().flatten() {
+            write_int(&mut r, symbol(s), "Rule")?;
+        }
+        for &s in start {
+            write_int(&mut c, symbol(s), "Symbol")?;
+        }
+    }
+    c.flush()?;
+    r.flush()?;
+    Ok(())
+}
+
+/// Writes a 32-bit little-endian integer, failing if it does not fit into the C `int` Navarro's RePair uses
+fn write_int(out: &mut impl Write, value: usize, what: &'static str) -> Result<(), RReaderError> {
+    let value = i32::try_from(value).map_err(|_| RReaderError::TooLarge(what))?;
+    out.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::BufWriter};
+
+    use crate::{
+        coding::{
+            grammar_coder::{GrammarDecoder, GrammarEncoder},
+            navarro_repair_decoder::{IntWidth, NavarroIntRepairDecoder, NavarroRepairDecoder, RePairResult},
+        },
+        error::RReaderError,
+        grammar::Grammar,
+        native_repair,
+    };
+
+    use super::{NavarroIntRepairEncoder, NavarroRepairEncoder};
+
+    fn encode(gr: Grammar) -> Result<RePairResult, RReaderError> {
+        let mut res = RePairResult::default();
+        NavarroRepairEncoder::encode_to_files(gr, &mut res.file_c, &mut res.file_r)?;
+        Ok(res)
+    }
+
+    fn encode_ints(gr: Grammar) -> Result<RePairResult, RReaderError> {
+        let mut res = RePairResult::default();
+        NavarroIntRepairEncoder::encode_to_files(gr, &mut res.file_c, &mut res.file_r)?;
+        Ok(res)
+    }
+
+    #[test]
+    fn navarro_encode_test() {
+        let gr = Grammar::from_parts(vec![vec![103, 97], vec![97, 99], vec![257, 101], vec![258, 256, 99, 256]], 3);
+        let res = encode(gr.clone()).unwrap();
+
+        let ints = |ints: &[u32]| ints.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut r_bytes = ints(&[4]);
+        r_bytes.extend_from_slice(b"aceg");
+        // The rules are renumbered, so that ac comes first
+        r_bytes.extend(ints(&[0, 1, 4, 2, 3, 0]));
+        assert_eq!(r_bytes, res.file_r, ".R file encoded incorrectly");
+        assert_eq!(ints(&[5, 6, 1, 6]), res.file_c, ".C file encoded incorrectly");
+
+        let decoded = NavarroRepairDecoder::decode(res);
+        assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+        assert_eq!(
+            gr.bytes().collect::<Vec<_>>(),
+            decoded.unwrap().bytes().collect::<Vec<_>>(),
+            "Decoded grammar producing the wrong text"
+        );
+    }
+
+    #[test]
+    fn navarro_coding_decoding_test() {
+        let text = b"abracadabra, abracadabra!\n\x00\xff abracadabra";
+        // The text twice in a long rule, which has to be split into pairs
+        let long = Grammar::from_parts(vec![vec![257, 257], text.map(usize::from).to_vec()], 0);
+        for gr in [native_repair::repair_bytes(text), long] {
+            let gr_text = gr.bytes().collect::<Vec<_>>();
+            let decoded = NavarroRepairDecoder::decode(encode(gr).unwrap());
+            assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+            let decoded = decoded.unwrap();
+            assert!(
+                decoded.rules()[..decoded.rule_count() - 1].iter().all(|rule| rule.len() == 2),
+                "Rule other than the start rule is no pair"
+            );
+            assert_eq!(gr_text, decoded.bytes().collect::<Vec<_>>(), "Text not decoded correctly");
+        }
+    }
+
+    #[test]
+    fn navarro_int_coding_decoding_test() {
+        let symbols = (0..3000u32).map(|i| i * i % 7001 * 10).collect::<Vec<_>>();
+        let gr = native_repair::repair_ints(&symbols);
+        let alphabet_size = gr.alphabet_size() as u32;
+        let res = encode_ints(gr).unwrap();
+        assert_eq!(alphabet_size.to_le_bytes(), res.file_r[..4], "Alphabet size encoded incorrectly");
+
+        let decoded = NavarroIntRepairDecoder::decode(res);
+        assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Symbols not decoded correctly"
+        );
+    }
+
+    #[test]
+    fn navarro_files_test() {
+        let dir = std::env::temp_dir().join(format!("rreader-navarro-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("text");
+
+        let text = b"to be or not to be, that is the question";
+        let gr = native_repair::repair_bytes(text);
+        let c = File::create(dir.join("text.C")).unwrap();
+        let r = File::create(dir.join("text.R")).unwrap();
+        NavarroRepairEncoder::encode_to_files(gr.clone(), BufWriter::new(c), BufWriter::new(r)).unwrap();
+        assert_eq!(
+            encode(gr).unwrap().file_r,
+            std::fs::read(dir.join("text.R")).unwrap(),
+            ".R file written incorrectly"
+        );
+        let decoded = NavarroRepairDecoder::decode(base.as_path());
+        assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+        assert_eq!(text.to_vec(), decoded.unwrap().bytes().collect::<Vec<_>>(), "Text not decoded correctly");
+
+        let missing = NavarroRepairDecoder::decode(dir.join("missing").as_path());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(missing, Err(RReaderError::IO(_))), "Missing files decoded");
+    }
+
+    /// Encodes the grammar with any encoder, as code generic over the format would
+    fn encode_bundle<C: GrammarEncoder<EncodeErr = RReaderError>>(gr: Grammar) -> RePairResult {
+        let mut buf = vec![];
+        C::encode(gr, &mut buf).unwrap();
+        RePairResult::read_bundle(buf.as_slice(), IntWidth::U32).unwrap()
+    }
+
+    #[test]
+    fn navarro_grammar_encoder_test() {
+        let text = b"abracadabra, abracadabra!";
+        let gr = native_repair::repair_bytes(text);
+        let res = encode_bundle::<NavarroRepairEncoder>(gr.clone());
+        let files = encode(gr).unwrap();
+        assert_eq!((&files.file_c, &files.file_r), (&res.file_c, &res.file_r), "Bundle holding different files");
+        let decoded = NavarroRepairDecoder::decode(res);
+        assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+        assert_eq!(text.to_vec(), decoded.unwrap().bytes().collect::<Vec<_>>(), "Text not decoded correctly");
+
+        let symbols = [u32::MAX >> 2, 0, u32::MAX >> 2, 0, 7];
+        let res = encode_bundle::<NavarroIntRepairEncoder>(native_repair::repair_ints(&symbols));
+        let decoded = NavarroIntRepairDecoder::decode(res);
+        assert!(decoded.is_ok(), "Error decoding grammar: {:?}", decoded.err());
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            decoded.unwrap().produce_source_symbols(),
+            "Symbols not decoded correctly"
+        );
+
+        let mut buf = vec![];
+        NavarroRepairEncoder::encode(Grammar::from_parts(vec![vec![97, 98]], 0), &mut buf).unwrap();
+        assert!(
+            RePairResult::read_bundle(&buf[..buf.len() - 9], IntWidth::U32).is_err(),
+            "Bundle ending within the .R file read"
+        );
+    }
+
+    #[test]
+    fn navarro_too_large_test() {
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![300, 2]], 0, 1000);
+        assert!(
+            matches!(encode(gr.clone()), Err(RReaderError::TooLarge(_))),
+            "Grammar over more than bytes encoded"
+        );
+        assert!(encode_ints(gr).is_ok(), "Grammar over integers not encoded");
+
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![1 << 31, 2]], 0, (1 << 31) + 1);
+        assert!(
+            matches!(encode_ints(gr), Err(RReaderError::TooLarge(_))),
+            "Alphabet not fitting into 32 bits encoded"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::io::{Error, ErrorKind};
+
+use bitstream_io::{BitRead, BitWrite};
+
+/// A code for non-negative integers written into a bit stream
+pub trait NumberEncoder {
+    /// Writes the number. Fails with [`ErrorKind::InvalidInput`] if the code cannot represent it.
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()>;
+    /// The number of bits the number is written with
+    fn number_cost(value: u64) -> u64;
+}
+
+/// Reads the numbers written by the [`NumberEncoder`] of the same type
+pub trait NumberDecoder {
+    /// Reads a number. Fails with [`ErrorKind::InvalidData`] if the bits do not encode a number.
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64>;
+}
+
+/// Every number takes `BITS` bits, so only numbers below `2^BITS` can be written
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Fixed<const BITS: u32>;
+
+/// Elias gamma code of the number plus one: `floor(log2(n + 1))` zeros, followed by the binary representation of `n + 1`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Gamma;
+
+/// Elias delta code of the number plus one: the length of `n + 1` in gamma code,
+/// followed by the binary representation of `n + 1` without its leading one
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Delta;
+
+/// Golomb code with divisor `M`: the quotient in unary, as ones ended by a zero, followed by the remainder in truncated binary
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Golomb<const M: u64>;
+
+/// Rice code, the Golomb code with divisor `2^K`: the quotient in unary followed by the `K` low bits
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rice<const K: u32>;
+
+/// Unsigned LEB128: groups of 7 bits, least significant first, each in a byte whose high bit is set if more groups follow
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Leb128;
+
+impl<const BITS: u32> NumberEncoder for Fixed<BITS> {
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        const { assert!(BITS <= 64, "Fixed-width numbers have at most 64 bits") };
+        if bit_len(value) > BITS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{value} does not fit into {BITS} bits"),
+            ));
+        }
+        write_bits(out, BITS, value)
+    }
+
+    fn number_cost(_value: u64) -> u64 {
+        BITS as u64
+    }
+}
+
+impl<const BITS: u32> NumberDecoder for Fixed<BITS> {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        read_bits(input, BITS)
+    }
+}
+
+impl NumberEncoder for Gamma {
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        let value = value.checked_add(1).ok_or_else(too_large)?;
+        let len = bit_len(value);
+        write_bits(out, len - 1, 0)?;
+        write_bits(out, len, value)
+    }
+
+    fn number_cost(value: u64) -> u64 {
+        2 * bit_len(value.saturating_add(1)) as u64 - 1
+    }
+}
+
+impl NumberDecoder for Gamma {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        let mut zeros = 0;
+        while !input.read_bit()? {
+            zeros += 1;
+            if zeros >= u64::BITS {
+                return Err(invalid("gamma code longer than 64 bits"));
+            }
+        }
+        Ok(((1 << zeros) | read_bits(input, zeros)?) - 1)
+    }
+}
+
+impl NumberEncoder for Delta {
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        let value = value.checked_add(1).ok_or_else(too_large)?;
+        let len = bit_len(value);
+        Gamma::encode_number(len as u64 - 1, out)?;
+        write_bits(out, len - 1, value & !(1 << (len - 1)))
+    }
+
+    fn number_cost(value: u64) -> u64 {
+        let len = bit_len(value.saturating_add(1)) as u64;
+        Gamma::number_cost(len - 1) + len - 1
+    }
+}
+
+impl NumberDecoder for Delta {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        let len = Gamma::decode_number(input)? + 1;
+        if len > u64::BITS as u64 {
+            return Err(invalid("delta code longer than 64 bits"));
+        }
+        let rest = read_bits(input, len as u32 - 1)?;
+        Ok(((1 << (len - 1)) | rest) - 1)
+    }
+}
+
+impl<const M: u64> Golomb<M> {
+    /// The remainders below the cutoff take one bit less than the others
+    const BITS: u32 = bit_len(M - 1);
+    const CUTOFF: u64 = (1 << Self::BITS) - M;
+}
+
+impl<const M: u64> NumberEncoder for Golomb<M> {
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        const { assert!(M > 0, "The Golomb divisor must be positive") };
+        write_unary(out, value / M)?;
+        let remainder = value % M;
+        if remainder < Self::CUTOFF {
+            write_bits(out, Self::BITS - 1, remainder)
+        } else {
+            write_bits(out, Self::BITS, remainder + Self::CUTOFF)
+        }
+    }
+
+    fn number_cost(value: u64) -> u64 {
+        let remainder_cost = if value % M < Self::CUTOFF { Self::BITS - 1 } else { Self::BITS };
+        value / M + 1 + remainder_cost as u64
+    }
+}
+
+impl<const M: u64> NumberDecoder for Golomb<M> {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        let quotient = read_unary(input)?;
+        let remainder = if Self::BITS == 0 {
+            0
+        } else {
+            let remainder = read_bits(input, Self::BITS - 1)?;
+            if remainder < Self::CUTOFF {
+                remainder
+            } else {
+                ((remainder << 1) | input.read_bit()? as u64) - Self::CUTOFF
+            }
+        };
+        quotient
+            .checked_mul(M)
+            .and_then(|value| value.checked_add(remainder))
+            .ok_or_else(|| invalid("Golomb code larger than 64 bits"))
+    }
+}
+
+impl<const K: u32> NumberEncoder for Rice<K> {
+    fn encode_number(value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        const { assert!(K < 64, "Rice codes have fewer than 64 low bits") };
+        write_unary(out, value >> K)?;
+        write_bits(out, K, value & ((1 << K) - 1))
+    }
+
+    fn number_cost(value: u64) -> u64 {
+        (value >> K) + 1 + K as u64
+    }
+}
+
+impl<const K: u32> NumberDecoder for Rice<K> {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        let quotient = read_unary(input)?;
+        if bit_len(quotient) + K > u64::BITS {
+            return Err(invalid("Rice code larger than 64 bits"));
+        }
+        Ok((quotient << K) | read_bits(input, K)?)
+    }
+}
+
+impl NumberEncoder for Leb128 {
+    fn encode_number(mut value: u64, out: &mut impl BitWrite) -> std::io::Result<()> {
+        while value >= 0x80 {
+            out.write(8, (value & 0x7f) as u8 | 0x80)?;
+            value >>= 7;
+        }
+        out.write(8, value as u8)
+    }
+
+    fn number_cost(value: u64) -> u64 {
+        8 * bit_len(value).div_ceil(7).max(1) as u64
+    }
+}
+
+impl NumberDecoder for Leb128 {
+    fn decode_number(input: &mut impl BitRead) -> std::io::Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = input.read::<u8>(8)?;
+            let group = (byte & 0x7f) as u64;
+            if shift >= u64::BITS || (group << shift) >> shift != group {
+                return Err(invalid("LEB128 number larger than 64 bits"));
+            }
+            value |= group << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+}
+
+/// The number of bits in the binary representation of the value, which is none for 0
+const fn bit_len(value: u64) -> u32 {
+    u64::BITS - value.leading_zeros()
+}
+
+/// The number of bits needed to write any of the values `0..count`, which is none for a single value
+pub(super) fn bits_for(count: usize) -> u32 {
+    bit_len(count.saturating_sub(1) as u64)
+}
+
+/// Writes the low bits of the value, which may be none
+pub(super) fn write_bits(out: &mut impl BitWrite, bits: u32, value: u64) -> std::io::Result<()> {
+    if bits == 0 {
+        return Ok(());
+    }
+    out.write(bits, value)
+}
+
+pub(super) fn read_bits(input: &mut impl BitRead, bits: u32) -> std::io::Result<u64> {
+    if bits == 0 {
+        return Ok(0);
+    }
+    input.read(bits)
+}
+
+/// Writes the value as that many ones, ended by a zero
+fn write_unary(out: &mut impl BitWrite, value: u64) -> std::io::Result<()> {
+    for _ in 0..value {
+        out.write_bit(true)?;
+    }
+    out.write_bit(false)
+}
+
+fn read_unary(input: &mut impl BitRead) -> std::io::Result<u64> {
+    let mut value = 0;
+    while input.read_bit()? {
+        value += 1;
+    }
+    Ok(value)
+}
+
+fn too_large() -> Error {
+    Error::new(ErrorKind::InvalidInput, "number too large for the code")
+}
+
+/// An error for input that does not follow the format
+pub(super) fn invalid(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod test {
+    use bitstream_io::{BigEndian, BitReader, BitWrite, BitWriter};
+
+    use super::{Delta, Fixed, Gamma, Golomb, Leb128, NumberDecoder, NumberEncoder, Rice};
+
+    const VALUES: [u64; 14] = [0, 1, 2, 3, 4, 5, 7, 8, 127, 128, 300, 65535, 1 << 40, u64::MAX - 1];
+
+    /// Encodes and decodes the values, checking the encoded length against the cost
+    fn roundtrip<C: NumberEncoder + NumberDecoder>(values: &[u64]) {
+        let mut buf = vec![];
+        let mut writer = BitWriter::endian(&mut buf, BigEndian);
+        let mut bits = 0;
+        for &value in values {
+            C::encode_number(value, &mut writer).unwrap();
+            bits += C::number_cost(value);
+        }
+        writer.byte_align().unwrap();
+        assert_eq!(bits.div_ceil(8), buf.len() as u64, "Cost differs from the encoded length");
+
+        let mut reader = BitReader::endian(buf.as_slice(), BigEndian);
+        for &value in values {
+            assert_eq!(value, C::decode_number(&mut reader).unwrap(), "Number decoded incorrectly");
+        }
+    }
+
+    #[test]
+    fn fixed_test() {
+        roundtrip::<Fixed<64>>(&VALUES);
+        roundtrip::<Fixed<9>>(&VALUES[..11]);
+        roundtrip::<Fixed<0>>(&[0, 0]);
+
+        let mut writer = BitWriter::endian(vec![], BigEndian);
+        let result = Fixed::<8>::encode_number(256, &mut writer);
+        assert_eq!(
+            Some(std::io::ErrorKind::InvalidInput),
+            result.err().map(|err| err.kind()),
+            "Too large number written"
+        );
+    }
+
+    #[test]
+    fn gamma_test() {
+        roundtrip::<Gamma>(&VALUES);
+        let mut writer = BitWriter::endian(vec![], BigEndian);
+        assert!(Gamma::encode_number(u64::MAX, &mut writer).is_err(), "Too large number written");
+
+        let mut writer = BitWriter::endian(vec![], BigEndian);
+        for value in 0..4 {
+            Gamma::encode_number(value, &mut writer).unwrap();
+        }
+        writer.byte_align().unwrap();
+        assert_eq!(vec![0b1010_0110, 0b0100_0000], writer.into_writer(), "Gamma code incorrect");
+    }
+
+    #[test]
+    fn delta_test() {
+        roundtrip::<Delta>(&VALUES);
+        assert_eq!(1, Delta::number_cost(0), "Delta code of 1 not a single bit");
+        assert_eq!(9, Delta::number_cost(16), "Delta code of 17 incorrect");
+    }
+
+    #[test]
+    fn golomb_test() {
+        roundtrip::<Golomb<1>>(&VALUES[..10]);
+        roundtrip::<Golomb<3>>(&VALUES[..12]);
+        roundtrip::<Golomb<10>>(&VALUES[..12]);
+        roundtrip::<Golomb<16>>(&VALUES[..12]);
+        // Truncated binary remainders for divisor 3 are 0, 10 and 11
+        assert_eq!(
+            [2, 3, 3, 3],
+            [0, 1, 2, 3].map(Golomb::<3>::number_cost),
+            "Golomb code lengths incorrect"
+        );
+    }
+
+    #[test]
+    fn rice_test() {
+        roundtrip::<Rice<0>>(&VALUES[..10]);
+        roundtrip::<Rice<4>>(&VALUES[..12]);
+        roundtrip::<Rice<40>>(&VALUES[..13]);
+        assert_eq!(Golomb::<16>::number_cost(300), Rice::<4>::number_cost(300), "Rice code differs from Golomb code");
+    }
+
+    #[test]
+    fn leb128_test() {
+        roundtrip::<Leb128>(&VALUES);
+        roundtrip::<Leb128>(&[u64::MAX]);
+
+        let mut writer = BitWriter::endian(vec![], BigEndian);
+        Leb128::encode_number(624485, &mut writer).unwrap();
+        assert_eq!(vec![0xe5, 0x8e, 0x26], writer.into_writer(), "LEB128 code incorrect");
+
+        let overlong = [0xff; 10].into_iter().chain([0x7f]).collect::<Vec<_>>();
+        let mut reader = BitReader::endian(overlong.as_slice(), BigEndian);
+        assert!(Leb128::decode_number(&mut reader).is_err(), "Too large LEB128 number decoded");
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the tests of the coders
+
+use crate::grammar::Grammar;
+
+use super::grammar_coder::GrammarEncoder;
+
+/// Encodes a copy of the grammar into a buffer
+pub(super) fn encode<C: GrammarEncoder<EncodeErr = std::io::Error>>(gr: &Grammar) -> Vec<u8> {
+    let mut buf = vec![];
+    C::encode(gr.clone(), &mut buf).unwrap();
+    buf
+}
+
+/// 20000 bytes over 21 letters, with enough repetition for RePair to find plenty of rules
+pub(super) fn repetitive_text() -> Vec<u8> {
+    (0..20000usize).map(|i| b'a' + (i % 13 + i % 7 + i / 1000 % 3) as u8).collect()
+}
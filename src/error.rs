@@ -1,19 +1,52 @@
-use std::ffi::NulError;
+use std::fmt::Display;
 
 #[derive(Debug)]
 pub enum RReaderError {
     IO(std::io::Error),
     NoInputFile,
     MissingInput(&'static str),
+    /// A value is too large for the format or platform it has to be stored in
+    TooLarge(&'static str),
     RePair(RePairError),
+    InvalidGrammar(GrammarError),
+    /// A line of a textual grammar could not be parsed
+    Parse { line: usize, reason: String },
+    /// The input is not in the given binary format, or in a version of it that is not supported
+    UnsupportedFormat { format: &'static str, version: Option<u32> },
 }
 
+impl Display for RReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{err}"),
+            Self::NoInputFile => write!(f, "no input file given"),
+            Self::MissingInput(what) => write!(f, "input ended early, missing {what}"),
+            Self::TooLarge(what) => write!(f, "{what} is too large to be represented"),
+            Self::RePair(err) => write!(f, "RePair failed: {err}"),
+            Self::InvalidGrammar(err) => write!(f, "invalid grammar: {err}"),
+            Self::Parse { line, reason } => write!(f, "cannot parse line {line}: {reason}"),
+            Self::UnsupportedFormat { format, version: None } => write!(f, "input is not in the {format} format"),
+            Self::UnsupportedFormat { format, version: Some(version) } => {
+                write!(f, "unsupported format version {version} of the {format} format")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RReaderError {}
+
 impl From<std::io::Error> for RReaderError {
     fn from(err: std::io::Error) -> Self {
         Self::IO(err)
     }
 }
 
+impl From<GrammarError> for RReaderError {
+    fn from(err: GrammarError) -> Self {
+        Self::InvalidGrammar(err)
+    }
+}
+
 impl From<RePairError> for RReaderError {
     fn from(err: RePairError) -> Self {
         match err {
@@ -25,18 +58,72 @@ impl From<RePairError> for RReaderError {
 
 #[derive(Debug)]
 pub enum RePairError {
-    InvalidFileName(NulError),
+    /// The input file's metadata could not be read, e.g. because it does not exist
+    CannotStat(String, std::io::Error),
+    /// The input file exists but could not be read
+    CannotRead(String, std::io::Error),
+    /// An allocation inside the RePair implementation failed
+    OutOfMemory,
+    /// The RePair implementation returned a status code that is not known
+    UnknownStatus(i32),
+    /// The input is longer than the RePair implementation can index
+    InputTooLarge(usize),
+    /// The given RePair options are out of range
+    InvalidOptions(&'static str),
     IO(std::io::Error)
 }
 
-impl From<NulError> for RePairError {
-    fn from(err: NulError) -> Self {
-        Self::InvalidFileName(err)
+impl Display for RePairError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CannotStat(file, err) => write!(f, "cannot stat file {file}: {err}"),
+            Self::CannotRead(file, err) => write!(f, "cannot read file {file}: {err}"),
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::UnknownStatus(code) => write!(f, "unknown status code {code}"),
+            Self::InputTooLarge(len) => write!(f, "input of {len} bytes is too large"),
+            Self::InvalidOptions(reason) => write!(f, "invalid options: {reason}"),
+            Self::IO(err) => write!(f, "{err}"),
+        }
     }
 }
 
+impl std::error::Error for RePairError {}
+
 impl From<std::io::Error> for RePairError {
     fn from(err: std::io::Error) -> Self {
         Self::IO(err)
     }
 }
+
+/// A reason why a grammar does not describe a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// The start rule is not one of the rules
+    StartRuleOutOfRange { start_rule: usize, rule_count: usize },
+    /// A rule contains a nonterminal referring to a rule that does not exist
+    DanglingReference { rule: usize, symbol: usize },
+    /// A rule other than the start rule has no symbols
+    EmptyRule(usize),
+    /// The rule derives itself
+    Cycle(usize),
+    /// The rule can't be reached from the start rule
+    UnreachableRule(usize),
+}
+
+impl Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StartRuleOutOfRange { start_rule, rule_count } => {
+                write!(f, "start rule {start_rule} does not exist, there are {rule_count} rules")
+            }
+            Self::DanglingReference { rule, symbol } => {
+                write!(f, "rule {rule} refers to nonexistent rule with symbol {symbol}")
+            }
+            Self::EmptyRule(rule) => write!(f, "rule {rule} is empty"),
+            Self::Cycle(rule) => write!(f, "rule {rule} derives itself"),
+            Self::UnreachableRule(rule) => write!(f, "rule {rule} is unreachable from the start rule"),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
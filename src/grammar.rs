@@ -1,104 +1,605 @@
-use std::{io::Write, ops::Index};
+use crate::{coding::grammar_coder::CostModel, error::GrammarError};
 
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    iter::Peekable,
+    ops::{Bound, Index, RangeBounds},
+    sync::OnceLock,
+};
+
+/// The alphabet size of grammars over bytes, so the symbol of rule `i` is `RULE_OFFSET + i`
 pub const RULE_OFFSET: usize = 256;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// A grammar whose terminals are `0..alphabet_size` and whose nonterminals are `alphabet_size + rule`
+#[derive(Clone)]
 pub struct Grammar {
     rules: Vec<Vec<usize>>,
     start_rule: usize,
+    alphabet_size: usize,
+    /// Computed when first needed, and reset whenever the rules or the start rule change
+    stats: OnceLock<Stats>,
+}
+
+/// Statistics about a single rule of a grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RuleStats {
+    /// The length of the rule's expansion, saturating at `usize::MAX`
+    pub expansion_len: usize,
+    /// The height of the rule's derivation tree. A rule of only terminals has height 1.
+    pub height: usize,
+    /// How often the rule occurs on the right-hand sides of the grammar
+    pub references: usize,
+}
+
+/// Annotations appended as a `#` comment to each rule by [`Grammar::write_text`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextOptions {
+    /// Show the length of the rule's expansion
+    pub expansion_len: bool,
+    /// Show at most this many symbols of the rule's expansion
+    pub preview: Option<usize>,
+}
+
+/// The effect of [`Grammar::rebalance`] on a grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceReport {
+    /// The height of the grammar before rebalancing
+    pub height_before: usize,
+    /// The height of the grammar after rebalancing
+    pub height_after: usize,
+    /// The number of rules before rebalancing
+    pub rules_before: usize,
+    /// The number of rules after rebalancing
+    pub rules_after: usize,
+}
+
+impl std::fmt::Display for BalanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "height {} -> {}, {} -> {} rules",
+            self.height_before, self.height_after, self.rules_before, self.rules_after
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Stats {
+    rules: Vec<RuleStats>,
+    /// The offset of each symbol of the start rule in the text, followed by the text length.
+    /// The start rule is usually by far the longest, so it is binary searched in these for random access.
+    start_offsets: Vec<usize>,
 }
 
 impl Grammar {
     pub fn empty() -> Self {
-        Grammar {
-            rules: vec![],
-            start_rule: 0,
-        }
+        Self::from_parts(vec![], 0)
     }
 
+    #[deprecated(note = "assumes an alphabet of bytes, use `is_terminal_symbol` instead")]
     pub fn is_terminal(symbol: usize) -> bool {
         symbol < RULE_OFFSET
     }
 
+    #[deprecated(note = "assumes an alphabet of bytes, use `is_nonterminal_symbol` instead")]
     pub fn is_nonterminal(symbol: usize) -> bool {
         symbol >= RULE_OFFSET
     }
 
+    /// Whether the symbol is a terminal of this grammar's alphabet
+    pub fn is_terminal_symbol(&self, symbol: usize) -> bool {
+        symbol < self.alphabet_size
+    }
+
+    /// Whether the symbol refers to a rule of this grammar
+    pub fn is_nonterminal_symbol(&self, symbol: usize) -> bool {
+        symbol >= self.alphabet_size
+    }
+
+    /// The symbol referring to the given rule
+    pub fn nonterminal(&self, rule: usize) -> usize {
+        self.alphabet_size + rule
+    }
+
+    /// The rule the given symbol refers to, or `None` if it is a terminal
+    pub fn rule_index(&self, symbol: usize) -> Option<usize> {
+        symbol.checked_sub(self.alphabet_size)
+    }
+
+    /// Creates a grammar over bytes
     pub fn from_parts(rules: Vec<Vec<usize>>, start_rule: usize) -> Self {
-        Self { rules, start_rule }
+        Self::from_parts_with_alphabet(rules, start_rule, RULE_OFFSET)
+    }
+
+    /// Creates a grammar whose terminals are `0..alphabet_size`
+    pub fn from_parts_with_alphabet(rules: Vec<Vec<usize>>, start_rule: usize, alphabet_size: usize) -> Self {
+        Self {
+            rules,
+            start_rule,
+            alphabet_size,
+            stats: OnceLock::new(),
+        }
+    }
+
+    pub fn alphabet_size(&self) -> usize {
+        self.alphabet_size
+    }
+
+    pub fn start_rule(&self) -> usize {
+        self.start_rule
     }
 
     pub fn set_start_rule(&mut self, new_start_rule: usize) {
         self.start_rule = new_start_rule;
+        self.stats = OnceLock::new();
     }
 
     pub fn rule_count(&self) -> usize {
         self.rules.len()
     }
 
-    fn renumber_internal(&self, id: usize, renumbering: &mut [usize], count: &mut usize) {
-        self[id]
-            .iter()
-            .copied()
-            .filter(|&symbol| Grammar::is_nonterminal(symbol))
-            .map(|symbol| symbol - RULE_OFFSET)
-            .for_each(|symbol| self.renumber_internal(symbol, renumbering, count));
+    /// Checks that the grammar describes a text, so that expanding it can neither panic nor loop forever.
+    /// A grammar without rules and an empty start rule are valid, and describe the empty text.
+    pub fn validate(&self) -> Result<(), GrammarError> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        let rule_count = self.rule_count();
+        if self.start_rule >= rule_count {
+            return Err(GrammarError::StartRuleOutOfRange {
+                start_rule: self.start_rule,
+                rule_count,
+            });
+        }
+        for (id, rule) in self.rules.iter().enumerate() {
+            if rule.is_empty() && id != self.start_rule {
+                return Err(GrammarError::EmptyRule(id));
+            }
+            for &symbol in rule.iter() {
+                if self.rule_index(symbol).is_some_and(|child| child >= rule_count) {
+                    return Err(GrammarError::DanglingReference { rule: id, symbol });
+                }
+            }
+        }
 
-        // If the renumbering is not set yet, set it
-        if renumbering[id] == usize::MAX {
-            renumbering[id] = *count;
-            *count += 1;
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Visit {
+            New,
+            Active,
+            Done,
+        }
+        let mut visits = vec![Visit::New; rule_count];
+        let mut unreachable = None;
+        // Search from the start rule first to find the unreachable rules,
+        // then from all others so that cycles among those are found as well
+        for root in std::iter::once(self.start_rule).chain(0..rule_count) {
+            if visits[root] != Visit::New {
+                continue;
+            }
+            visits[root] = Visit::Active;
+            // The rule and the position of the next symbol to look at in it
+            let mut stack = vec![(root, 0)];
+            while let Some((rule, pos)) = stack.pop() {
+                let symbol = match self.rules[rule].get(pos) {
+                    Some(&symbol) => symbol,
+                    None => {
+                        visits[rule] = Visit::Done;
+                        continue;
+                    }
+                };
+                stack.push((rule, pos + 1));
+                if let Some(child) = self.rule_index(symbol) {
+                    match visits[child] {
+                        Visit::New => {
+                            visits[child] = Visit::Active;
+                            stack.push((child, 0));
+                        }
+                        Visit::Active => return Err(GrammarError::Cycle(child)),
+                        Visit::Done => {}
+                    }
+                }
+            }
+            if root == self.start_rule {
+                unreachable = visits.iter().position(|&visit| visit == Visit::New);
+            }
+        }
+        match unreachable {
+            Some(rule) => Err(GrammarError::UnreachableRule(rule)),
+            None => Ok(()),
         }
     }
 
-    pub fn renumber(&mut self) {
+    /// Lists the rules reachable from the roots, each after all rules it refers to.
+    /// Rules are listed once, and a rule referring back to one of its ancestors is cut off there.
+    fn post_order(&self, roots: impl IntoIterator<Item = usize>) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.rule_count());
+        let mut seen = vec![false; self.rule_count()];
+        // The rule and the position of the next symbol to look at in it
+        let mut stack = vec![];
+        for root in roots {
+            if seen[root] {
+                continue;
+            }
+            seen[root] = true;
+            stack.push((root, 0));
+            while let Some((rule, pos)) = stack.pop() {
+                match self.rules[rule].get(pos) {
+                    Some(&symbol) => {
+                        stack.push((rule, pos + 1));
+                        if let Some(child) = self.rule_index(symbol) {
+                            if !seen[child] {
+                                seen[child] = true;
+                                stack.push((child, 0));
+                            }
+                        }
+                    }
+                    None => order.push(rule),
+                }
+            }
+        }
+        order
+    }
+
+    /// Renumbers the rules so that each rule comes after the rules it refers to, and the start rule is last.
+    /// Rules unreachable from the start rule are numbered after the reachable ones, but still before the start rule.
+    /// Returns the permutation that was applied, which maps each old rule id to its new one.
+    pub fn renumber(&mut self) -> Vec<usize> {
         if self.rules.is_empty() {
-            return;
+            return vec![];
+        }
+
+        // The rules reachable from the start rule come first, and end with the start rule.
+        // Any unreachable rules follow, and the start rule is moved behind them.
+        let mut order = self.post_order(std::iter::once(self.start_rule).chain(0..self.rule_count()));
+        if order.last() != Some(&self.start_rule) {
+            order.retain(|&rule| rule != self.start_rule);
+            order.push(self.start_rule);
         }
 
-        let mut renumbering = vec![usize::MAX; self.rule_count()];
-        let mut cnt = 0;
+        let mut renumbering = vec![0; self.rule_count()];
+        for (new_id, rule) in order.into_iter().enumerate() {
+            renumbering[rule] = new_id;
+        }
+        self.remap(|rule| Some(renumbering[rule]));
+        renumbering
+    }
+
+    /// Removes the rules that are unreachable from the start rule, keeping the order of the others.
+    /// Returns the new id of each old rule, or `None` if it was removed.
+    /// The coders prune before encoding, as unreachable rules would only take up space and make the decoded grammar invalid.
+    pub fn prune(&mut self) -> Vec<Option<usize>> {
+        if self.rules.is_empty() {
+            return vec![];
+        }
 
-        self.renumber_internal(self.start_rule, &mut renumbering, &mut cnt);
+        let mut reachable = vec![false; self.rule_count()];
+        for rule in self.post_order([self.start_rule]) {
+            reachable[rule] = true;
+        }
+        let mut count = 0;
+        let mapping = reachable
+            .into_iter()
+            .map(|reachable| {
+                reachable.then(|| {
+                    count += 1;
+                    count - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        self.remap(|rule| mapping[rule]);
+        mapping
+    }
 
+    /// Replaces references to rules by the rules' symbols where that makes the grammar smaller in the encoding `C`.
+    /// Rules referenced only once are always inlined, others only if they have at most `max_len` symbols.
+    /// The start rule and unreferenced rules are kept. Returns the number of rules that were inlined.
+    pub fn inline<C: CostModel>(&mut self, max_len: usize) -> usize {
+        if self.rules.is_empty() {
+            return 0;
+        }
         let rule_count = self.rule_count();
+        let mut references = vec![0u64; rule_count];
+        for &symbol in self.rules.iter().flatten() {
+            if let Some(rule) = self.rule_index(symbol) {
+                references[rule] += 1;
+            }
+        }
+        let rule_cost = C::rule_cost(self);
+        let mut inlined = vec![false; rule_count];
+
+        // Children are decided first, so the symbols of the rules they are inlined into are final when those are decided
+        for rule in self.post_order(0..rule_count) {
+            if self.rules[rule]
+                .iter()
+                .any(|&symbol| self.rule_index(symbol).is_some_and(|child| inlined[child]))
+            {
+                let old_symbols = std::mem::take(&mut self.rules[rule]);
+                let mut symbols = Vec::with_capacity(old_symbols.len());
+                for symbol in old_symbols {
+                    match self.rule_index(symbol) {
+                        Some(child) if inlined[child] => symbols.extend_from_slice(&self.rules[child]),
+                        _ => symbols.push(symbol),
+                    }
+                }
+                self.rules[rule] = symbols;
+            }
+
+            let count = references[rule];
+            if rule == self.start_rule || count == 0 {
+                continue;
+            }
+            let symbols_cost = self.rules[rule]
+                .iter()
+                .map(|&symbol| C::symbol_cost(self, symbol))
+                .sum::<u64>();
+            let kept_cost = symbols_cost + rule_cost + count * C::symbol_cost(self, self.nonterminal(rule));
+            let inlined_cost = count * symbols_cost;
+            inlined[rule] = count == 1 || (self.rules[rule].len() <= max_len && inlined_cost <= kept_cost);
+        }
+
+        let mut new_id = 0;
+        let mapping = inlined
+            .iter()
+            .map(|&inlined| {
+                (!inlined).then(|| {
+                    new_id += 1;
+                    new_id - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        self.remap(|rule| mapping[rule]);
+        rule_count - self.rule_count()
+    }
+
+    /// Whether the grammar is a straight-line program, where every rule consists of exactly two symbols.
+    /// As an exception, the start rule of a text shorter than two symbols consists of just that text.
+    pub fn is_slp(&self) -> bool {
+        self.rules.iter().enumerate().all(|(id, rule)| {
+            rule.len() == 2
+                || (id == self.start_rule && rule.len() < 2 && rule.iter().all(|&symbol| self.is_terminal_symbol(symbol)))
+        })
+    }
+
+    /// Converts the grammar into a straight-line program producing the same text, see [`Grammar::is_slp`].
+    /// Rules with fewer than two symbols are inlined and unreachable rules are removed.
+    /// Longer rules are split into new rules appended to the grammar. If `balanced` is set,
+    /// they are split into balanced trees, which adds only logarithmically to the height.
+    /// Otherwise, they are split into left-deep chains like `((a b) c) d`.
+    pub fn to_slp(&self, balanced: bool) -> Result<Grammar, GrammarError> {
+        self.binarize(balanced, true)
+    }
+
+    /// Converts the grammar into the form RePair produces, where every rule but the start rule consists of two symbols.
+    /// Like [`Grammar::to_slp`] with balanced splits, except that the start rule keeps its symbols.
+    pub fn to_pair_rules(&self) -> Result<Grammar, GrammarError> {
+        self.binarize(true, false)
+    }
+
+    fn binarize(&self, balanced: bool, split_start: bool) -> Result<Grammar, GrammarError> {
+        self.validate()?;
+        let mut slp = self.clone();
+        if slp.rules.is_empty() {
+            return Ok(slp);
+        }
+        slp.prune();
+
+        // Children come first, so the rules inlined into a rule are final
+        for rule in slp.post_order([slp.start_rule]) {
+            if slp.rules[rule]
+                .iter()
+                .any(|&symbol| slp.rule_index(symbol).is_some_and(|child| slp.rules[child].len() < 2))
+            {
+                let old_symbols = std::mem::take(&mut slp.rules[rule]);
+                let mut symbols = Vec::with_capacity(old_symbols.len());
+                for symbol in old_symbols {
+                    match slp.rule_index(symbol) {
+                        Some(child) if slp.rules[child].len() < 2 => symbols.extend_from_slice(&slp.rules[child]),
+                        _ => symbols.push(symbol),
+                    }
+                }
+                slp.rules[rule] = symbols;
+            }
+        }
+        // A start rule consisting of a single rule is replaced by that rule, which has at least two symbols now
+        let start = &slp.rules[slp.start_rule];
+        if let [symbol] = start[..] {
+            if let Some(child) = slp.rule_index(symbol) {
+                slp.start_rule = child;
+            }
+        }
+        // The rules with less than two symbols are unreachable now
+        slp.prune();
+
+        for rule in 0..slp.rule_count() {
+            if slp.rules[rule].len() <= 2 || (rule == slp.start_rule && !split_start) {
+                continue;
+            }
+            let symbols = std::mem::take(&mut slp.rules[rule]);
+            slp.rules[rule] = if balanced {
+                slp.split_balanced(symbols)
+            } else {
+                slp.split_left_deep(symbols)
+            };
+        }
+        slp.stats = OnceLock::new();
+        Ok(slp)
+    }
+
+    /// Pairs up neighboring symbols into new rules until two symbols remain
+    fn split_balanced(&mut self, mut symbols: Vec<usize>) -> Vec<usize> {
+        while symbols.len() > 2 {
+            symbols = symbols
+                .chunks(2)
+                .map(|chunk| match *chunk {
+                    [left, right] => self.push_pair(left, right),
+                    _ => chunk[0],
+                })
+                .collect();
+        }
+        symbols
+    }
+
+    /// Joins all symbols but the last into a chain of new rules, each adding one symbol to the previous
+    fn split_left_deep(&mut self, symbols: Vec<usize>) -> Vec<usize> {
+        let (&last, init) = symbols.split_last().expect("Splitting an empty rule");
+        let prefix = init[1..]
+            .iter()
+            .fold(init[0], |prefix, &symbol| self.push_pair(prefix, symbol));
+        vec![prefix, last]
+    }
+
+    /// Appends the rule `left right` and returns its symbol
+    fn push_pair(&mut self, left: usize, right: usize) -> usize {
+        self.rules.push(vec![left, right]);
+        self.nonterminal(self.rule_count() - 1)
+    }
+
+    /// Replaces the grammar by an equivalent straight-line program of height `O(log n)` for a text of length `n`.
+    /// Every rule is rebuilt bottom-up as an AVL grammar, in which the heights of the two symbols of each rule
+    /// differ by at most one. This bounds the height by about `1.44 log2(n)`, at the cost of `O(log n)` new rules
+    /// per symbol of the original grammar. Identical rules are shared and unreachable rules are removed.
+    ///
+    /// The grammar is left unchanged if it is invalid.
+    pub fn rebalance(&mut self) -> Result<BalanceReport, GrammarError> {
+        self.validate()?;
+        let height_before = self.height();
+        let rules_before = self.rule_count();
+        if self.rules.is_empty() {
+            return Ok(BalanceReport {
+                height_before,
+                height_after: height_before,
+                rules_before,
+                rules_after: rules_before,
+            });
+        }
+
+        let mut builder = AvlBuilder::new(self.alphabet_size);
+        // The balanced symbol each rule expands to, or None for an empty start rule
+        let mut balanced = vec![None; self.rule_count()];
+        for rule in self.post_order([self.start_rule]) {
+            let pieces = self.rules[rule]
+                .iter()
+                .map(|&symbol| match self.rule_index(symbol) {
+                    Some(child) => balanced[child].expect("Only the start rule may be empty"),
+                    None => symbol,
+                })
+                .collect();
+            balanced[rule] = builder.concat_all(pieces);
+        }
+
+        let mut rules = builder.rules;
+        let start_rule = match balanced[self.start_rule] {
+            Some(symbol) if symbol >= self.alphabet_size => symbol - self.alphabet_size,
+            // The text is shorter than two symbols, so the start rule is the text itself
+            start => {
+                rules.push(start.into_iter().collect());
+                rules.len() - 1
+            }
+        };
+        *self = Grammar::from_parts_with_alphabet(rules, start_rule, self.alphabet_size);
+        self.prune();
+        self.renumber();
+        Ok(BalanceReport {
+            height_before,
+            height_after: self.height(),
+            rules_before,
+            rules_after: self.rule_count(),
+        })
+    }
+
+    /// Moves each rule to the id given by the mapping and updates the references to it.
+    /// Rules mapped to `None` are removed, so no remaining rule may refer to them.
+    fn remap(&mut self, mapping: impl Fn(usize) -> Option<usize>) {
+        let new_count = (0..self.rule_count()).filter(|&rule| mapping(rule).is_some()).count();
 
         // Take out the old rules and replace them with a new vector
-        let old_rules = std::mem::replace(&mut self.rules, vec![vec![]; rule_count]);
+        let old_rules = std::mem::replace(&mut self.rules, vec![vec![]; new_count]);
 
         // Renumber the symbols in each rule and insert them into the appropriate place
         for (i, mut rule) in old_rules.into_iter().enumerate() {
-            rule.iter_mut()
-                .filter(|&&mut symbol| Grammar::is_nonterminal(symbol))
-                .for_each(|symbol| *symbol = renumbering[*symbol - RULE_OFFSET] + RULE_OFFSET);
-            self.rules[renumbering[i]] = rule;
+            let new_id = match mapping(i) {
+                Some(new_id) => new_id,
+                None => continue,
+            };
+            for symbol in rule.iter_mut() {
+                if let Some(id) = self.rule_index(*symbol) {
+                    *symbol = self.nonterminal(mapping(id).expect("Kept rule refers to a removed rule"));
+                }
+            }
+            self.rules[new_id] = rule;
         }
 
-        self.start_rule = self.rule_count() - 1;
+        self.start_rule = mapping(self.start_rule).expect("Start rule was removed");
+        self.stats = OnceLock::new();
     }
 
     pub fn consume(self) -> (Vec<Vec<usize>>, usize) {
         (self.rules, self.start_rule)
     }
 
+    /// Prints the rules to stdout, as formatted by [`Grammar::write_text`] without annotations
     pub fn print(&self) {
-        for (i, rule) in self.rules.iter().enumerate() {
-            let rule_str = rule
-                .iter()
-                .map(|&symbol| {
-                    if Grammar::is_terminal(symbol) {
-                        (symbol as u8 as char).to_string()
-                    } else {
-                        format!("R{}", symbol - RULE_OFFSET)
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join(" ");
-            println!("R{i} -> {rule_str}")
+        print!("{self}")
+    }
+
+    /// Writes one line `R<i> -> symbols` per rule. Nonterminals are written as `R<i>`.
+    /// Terminals of byte grammars are quoted like `'a'`, with `\n`, `\r`, `\t`, `\\` and `\'` escaped
+    /// and other bytes outside of printable ASCII written as `'\xNN'`. Terminals of larger alphabets are written as numbers.
+    /// The options can append a comment with each rule's expansion length and a preview of its expansion.
+    pub fn write_text(&self, mut out: impl Write, options: &TextOptions) -> std::io::Result<()> {
+        for rule in 0..self.rule_count() {
+            writeln!(out, "{}", self.rule_line(rule, options))?;
         }
+        Ok(())
     }
 
-    pub fn produce_source_string(self) -> Result<String, std::string::FromUtf8Error> {
+    fn rule_line(&self, rule: usize, options: &TextOptions) -> String {
+        let mut line = format!("R{rule} ->");
+        for &symbol in &self.rules[rule] {
+            line.push(' ');
+            match self.rule_index(symbol) {
+                Some(id) => line.push_str(&format!("R{id}")),
+                None if self.alphabet_size <= RULE_OFFSET => {
+                    line.push('\'');
+                    escape_byte(symbol as u8, '\'', &mut line);
+                    line.push('\'');
+                }
+                None => line.push_str(&symbol.to_string()),
+            }
+        }
+
+        if options.expansion_len || options.preview.is_some() {
+            line.push_str(" #");
+        }
+        if options.expansion_len {
+            line.push_str(&format!(" len {}", self.expansion_len(rule)));
+        }
+        if let Some(preview_len) = options.preview {
+            let mut preview = Symbols {
+                grammar: self,
+                stack: vec![(rule, 0)],
+            };
+            let shown = preview.by_ref().take(preview_len).collect::<Vec<_>>();
+            line.push(' ');
+            if self.alphabet_size <= RULE_OFFSET {
+                line.push('"');
+                for symbol in shown {
+                    escape_byte(symbol as u8, '"', &mut line);
+                }
+                line.push('"');
+            } else {
+                let numbers = shown.iter().map(ToString::to_string).collect::<Vec<_>>();
+                line.push_str(&format!("[{}]", numbers.join(" ")));
+            }
+            if preview.next().is_some() {
+                line.push_str("...");
+            }
+        }
+        line
+    }
+
+    pub fn produce_source_string(&self) -> Result<String, std::string::FromUtf8Error> {
         let mut vec = Vec::<u8>::new();
         // We're writing to a string. This shouldn't fail
         self.write_source_string(&mut vec)
@@ -106,37 +607,422 @@ impl Grammar {
         String::from_utf8(vec)
     }
 
-    pub fn write_source_string(mut self, mut out: impl Write) -> std::io::Result<()> {
-        if self.rule_count() == 0 {
-            return Ok(());
+    /// Writes the expansion of the grammar as bytes.
+    /// Fails with [`std::io::ErrorKind::InvalidData`] if it contains a terminal that is not a byte.
+    pub fn write_source_string(&self, mut out: impl Write) -> std::io::Result<()> {
+        std::io::copy(&mut self.reader(), &mut out)?;
+        Ok(())
+    }
+
+    /// Expands the grammar into the sequence of terminals it represents.
+    /// Unlike [`Grammar::produce_source_string`], this works for terminals that are not bytes.
+    pub fn produce_source_symbols(&self) -> Vec<usize> {
+        self.symbols().collect()
+    }
+
+    /// Lazily expands the grammar into the terminals it represents
+    pub fn symbols(&self) -> Symbols<'_> {
+        let stack = if self.rules.is_empty() {
+            vec![]
+        } else {
+            vec![(self.start_rule, 0)]
+        };
+        Symbols { grammar: self, stack }
+    }
+
+    /// Lazily expands a grammar over bytes into the text it represents.
+    ///
+    /// # Panics
+    /// If the grammar contains a terminal that is not a byte. Use [`Grammar::reader`] to get an error instead.
+    pub fn bytes(&self) -> Bytes<'_> {
+        Bytes { symbols: self.symbols() }
+    }
+
+    /// Lazily expands a grammar over bytes into the text it represents, as a [`Read`].
+    /// Reading fails with [`std::io::ErrorKind::InvalidData`] at a terminal that is not a byte.
+    pub fn reader(&self) -> GrammarReader<'_> {
+        GrammarReader {
+            symbols: self.symbols().peekable(),
         }
+    }
 
-        self.renumber();
+    /// Returns the terminal at position `i` of the text, or `None` if the text is shorter.
+    /// Takes time proportional to the height of the grammar times the length of its rules other than the start rule.
+    pub fn char_at(&self, i: usize) -> Option<usize> {
+        self.descend(i).map(|(terminal, _)| terminal)
+    }
 
-        fn expand(gr: &Grammar, id: usize, out: &mut impl Write) -> std::io::Result<()> {
-            for &symbol in gr.rules()[id].iter() {
-                if Grammar::is_terminal(symbol) {
-                    out.write_all(&[symbol as u8])?;
-                } else {
-                    expand(gr, symbol - RULE_OFFSET, out)?;
-                    //out.write_all(expand[symbol - RULE_OFFSET].as_bytes())?;
+    /// Returns the terminals in the given range of the text, which is cut off at the end of the text.
+    /// Takes time proportional to [`Grammar::char_at`] plus the length of the range.
+    pub fn extract(&self, range: impl RangeBounds<usize>) -> Vec<usize> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => usize::MAX,
+        };
+        if start >= end {
+            return vec![];
+        }
+        let (first, stack) = match self.descend(start) {
+            Some(found) => found,
+            None => return vec![],
+        };
+
+        // Continue the expansion where the descent left off, until the range or the text ends
+        let rest = Symbols { grammar: self, stack };
+        std::iter::once(first).chain(rest).take(end - start).collect()
+    }
+
+    /// Finds the terminal at position `i` of the text.
+    /// Also returns the rules and positions in them that the expansion continues with, innermost last.
+    fn descend(&self, mut i: usize) -> Option<(usize, Vec<(usize, usize)>)> {
+        if i >= self.text_len() {
+            return None;
+        }
+        let stats = self.stats();
+        let offsets = &stats.start_offsets;
+
+        let mut rule = self.start_rule;
+        let mut pos = offsets.partition_point(|&offset| offset <= i) - 1;
+        i -= offsets[pos];
+        let mut stack = vec![];
+        loop {
+            let symbol = self.rules[rule][pos];
+            stack.push((rule, pos + 1));
+            match self.rule_index(symbol) {
+                None => return Some((symbol, stack)),
+                Some(child) => {
+                    rule = child;
+                    pos = 0;
+                    while i >= self.symbol_len(&stats.rules, self.rules[rule][pos]) {
+                        i -= self.symbol_len(&stats.rules, self.rules[rule][pos]);
+                        pos += 1;
+                    }
                 }
             }
-            Ok(())
         }
+    }
 
-        expand(&self, self.start_rule, &mut out)
+    fn symbol_len(&self, stats: &[RuleStats], symbol: usize) -> usize {
+        self.rule_index(symbol).map_or(1, |rule| stats[rule].expansion_len)
+    }
+
+    /// The statistics of each rule
+    pub fn rule_stats(&self) -> &[RuleStats] {
+        &self.stats().rules
+    }
+
+    /// The length of the expansion of the given rule
+    pub fn expansion_len(&self, rule: usize) -> usize {
+        self.rule_stats()[rule].expansion_len
+    }
+
+    /// The height of the derivation tree of the start rule, or 0 if there are no rules
+    pub fn height(&self) -> usize {
+        if self.rules.is_empty() {
+            return 0;
+        }
+        self.rule_stats()[self.start_rule].height
+    }
+
+    /// The length of the text the grammar produces, without expanding it
+    pub fn text_len(&self) -> usize {
+        if self.rules.is_empty() {
+            return 0;
+        }
+        self.expansion_len(self.start_rule)
+    }
+
+    fn stats(&self) -> &Stats {
+        self.stats.get_or_init(|| {
+            let mut rules = vec![RuleStats::default(); self.rule_count()];
+            // Children come first, so their statistics are known when their parents need them
+            for rule in self.post_order(0..self.rule_count()) {
+                let mut stats = RuleStats {
+                    height: 1,
+                    ..rules[rule]
+                };
+                for &symbol in self.rules[rule].iter() {
+                    stats.expansion_len = stats.expansion_len.saturating_add(self.symbol_len(&rules, symbol));
+                    if let Some(child) = self.rule_index(symbol) {
+                        stats.height = stats.height.max(rules[child].height + 1);
+                        rules[child].references += 1;
+                    }
+                }
+                rules[rule] = stats;
+            }
+
+            let start = self.rules.get(self.start_rule).map_or(&[][..], |rule| &rule[..]);
+            let mut start_offsets = Vec::with_capacity(start.len() + 1);
+            let mut offset = 0usize;
+            start_offsets.push(offset);
+            for &symbol in start {
+                offset = offset.saturating_add(self.symbol_len(&rules, symbol));
+                start_offsets.push(offset);
+            }
+            Stats { rules, start_offsets }
+        })
     }
 
     pub fn rules(&self) -> &Vec<Vec<usize>> {
         &self.rules
     }
 
-    pub fn iter(&self) -> std::slice::Iter<Vec<usize>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Vec<usize>> {
         self.rules.iter()
     }
 }
 
+/// Iterator over the terminals a grammar expands to, created by [`Grammar::symbols`].
+/// Uses an explicit stack, so deep grammars can't overflow the call stack.
+#[derive(Debug, Clone)]
+pub struct Symbols<'a> {
+    grammar: &'a Grammar,
+    /// The rule and the position of the next symbol to expand in it, innermost last
+    stack: Vec<(usize, usize)>,
+}
+
+impl Iterator for Symbols<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while let Some((rule, pos)) = self.stack.pop() {
+            let symbol = match self.grammar.rules[rule].get(pos) {
+                Some(&symbol) => symbol,
+                None => continue,
+            };
+            self.stack.push((rule, pos + 1));
+            match self.grammar.rule_index(symbol) {
+                Some(child) => self.stack.push((child, 0)),
+                None => return Some(symbol),
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the text a grammar over bytes expands to, created by [`Grammar::bytes`]
+#[derive(Debug, Clone)]
+pub struct Bytes<'a> {
+    symbols: Symbols<'a>,
+}
+
+impl Iterator for Bytes<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.symbols.next().map(|symbol| {
+            u8::try_from(symbol).unwrap_or_else(|_| panic!("terminal {symbol} does not fit into a byte"))
+        })
+    }
+}
+
+/// Reads the text a grammar over bytes expands to, created by [`Grammar::reader`]
+#[derive(Debug, Clone)]
+pub struct GrammarReader<'a> {
+    symbols: Peekable<Symbols<'a>>,
+}
+
+impl Read for GrammarReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let symbol = match self.symbols.peek() {
+                Some(&symbol) => symbol,
+                None => break,
+            };
+            match u8::try_from(symbol) {
+                Ok(byte) => buf[written] = byte,
+                // Report the bytes before the terminal first, so none of them get lost
+                Err(_) if written > 0 => break,
+                Err(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("terminal {symbol} does not fit into a byte"),
+                    ))
+                }
+            }
+            self.symbols.next();
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+/// Builds the binary rules of an AVL grammar, in which the heights of the two symbols of each rule differ by at most one.
+/// Rules are never changed once built, so concatenations share all untouched subtrees with their inputs.
+struct AvlBuilder {
+    alphabet_size: usize,
+    rules: Vec<Vec<usize>>,
+    /// The height of each rule. Terminals have height 0.
+    heights: Vec<usize>,
+    /// The symbol of each rule by its two symbols, so identical rules are only built once
+    pairs: HashMap<(usize, usize), usize>,
+}
+
+impl AvlBuilder {
+    fn new(alphabet_size: usize) -> Self {
+        Self {
+            alphabet_size,
+            rules: vec![],
+            heights: vec![],
+            pairs: HashMap::new(),
+        }
+    }
+
+    fn height(&self, symbol: usize) -> usize {
+        symbol.checked_sub(self.alphabet_size).map_or(0, |rule| self.heights[rule])
+    }
+
+    /// The two symbols of the rule the symbol refers to. Only called on symbols of height at least 1.
+    fn children(&self, symbol: usize) -> (usize, usize) {
+        match self.rules[symbol - self.alphabet_size][..] {
+            [left, right] => (left, right),
+            _ => unreachable!("AVL rules have two symbols"),
+        }
+    }
+
+    /// The symbol of the rule `left right`, which is built if it does not exist yet
+    fn pair(&mut self, left: usize, right: usize) -> usize {
+        if let Some(&symbol) = self.pairs.get(&(left, right)) {
+            return symbol;
+        }
+        let symbol = self.alphabet_size + self.rules.len();
+        self.heights.push(self.height(left).max(self.height(right)) + 1);
+        self.rules.push(vec![left, right]);
+        self.pairs.insert((left, right), symbol);
+        symbol
+    }
+
+    /// Pairs two balanced symbols whose heights differ by at most two, rotating if they differ by two
+    fn join(&mut self, left: usize, right: usize) -> usize {
+        let (left_height, right_height) = (self.height(left), self.height(right));
+        if left_height.abs_diff(right_height) <= 1 {
+            self.pair(left, right)
+        } else if left_height < right_height {
+            let (a, b) = self.children(right);
+            if self.height(a) <= self.height(b) {
+                let inner = self.pair(left, a);
+                self.pair(inner, b)
+            } else {
+                let (a1, a2) = self.children(a);
+                let (inner_left, inner_right) = (self.pair(left, a1), self.pair(a2, b));
+                self.pair(inner_left, inner_right)
+            }
+        } else {
+            let (a, b) = self.children(left);
+            if self.height(b) <= self.height(a) {
+                let inner = self.pair(b, right);
+                self.pair(a, inner)
+            } else {
+                let (b1, b2) = self.children(b);
+                let (inner_left, inner_right) = (self.pair(a, b1), self.pair(b2, right));
+                self.pair(inner_left, inner_right)
+            }
+        }
+    }
+
+    /// Concatenates two balanced symbols into a balanced symbol.
+    /// The taller symbol's spine facing the other one is descended until the heights match,
+    /// and the symbols passed on the way are joined back in bottom-up.
+    fn concat(&mut self, left: usize, right: usize) -> usize {
+        let (left_height, right_height) = (self.height(left), self.height(right));
+        let mut passed = vec![];
+        if left_height > right_height + 1 {
+            let mut current = left;
+            while self.height(current) > right_height + 1 {
+                let (a, b) = self.children(current);
+                passed.push(a);
+                current = b;
+            }
+            let mut joined = self.pair(current, right);
+            while let Some(a) = passed.pop() {
+                joined = self.join(a, joined);
+            }
+            joined
+        } else if right_height > left_height + 1 {
+            let mut current = right;
+            while self.height(current) > left_height + 1 {
+                let (a, b) = self.children(current);
+                passed.push(b);
+                current = a;
+            }
+            let mut joined = self.pair(left, current);
+            while let Some(b) = passed.pop() {
+                joined = self.join(joined, b);
+            }
+            joined
+        } else {
+            self.pair(left, right)
+        }
+    }
+
+    /// Concatenates a sequence of balanced symbols, or returns `None` if it is empty.
+    /// Neighbors are concatenated level by level, so symbols of similar height are usually concatenated.
+    fn concat_all(&mut self, mut symbols: Vec<usize>) -> Option<usize> {
+        while symbols.len() > 1 {
+            symbols = symbols
+                .chunks(2)
+                .map(|chunk| match *chunk {
+                    [left, right] => self.concat(left, right),
+                    _ => chunk[0],
+                })
+                .collect();
+        }
+        symbols.pop()
+    }
+}
+
+impl PartialEq for Grammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules
+            && self.start_rule == other.start_rule
+            && self.alphabet_size == other.alphabet_size
+    }
+}
+
+impl Eq for Grammar {}
+
+impl std::fmt::Debug for Grammar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Grammar")
+            .field("rules", &self.rules)
+            .field("start_rule", &self.start_rule)
+            .field("alphabet_size", &self.alphabet_size)
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Grammar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for rule in 0..self.rule_count() {
+            writeln!(f, "{}", self.rule_line(rule, &TextOptions::default()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends the byte as it is written in a literal delimited by `quote`
+fn escape_byte(byte: u8, quote: char, out: &mut String) {
+    match byte {
+        b'\n' => out.push_str("\\n"),
+        b'\r' => out.push_str("\\r"),
+        b'\t' => out.push_str("\\t"),
+        b'\\' => out.push_str("\\\\"),
+        _ if byte as char == quote => {
+            out.push('\\');
+            out.push(quote);
+        }
+        b' '..=b'~' => out.push(byte as char),
+        _ => out.push_str(&format!("\\x{byte:02x}")),
+    }
+}
+
 impl Index<usize> for Grammar {
     type Output = [usize];
 
@@ -147,7 +1033,11 @@ impl Index<usize> for Grammar {
 
 #[cfg(test)]
 mod test {
-    use super::Grammar;
+    use std::{io::Read, sync::OnceLock};
+
+    use crate::{coding::grammar_tuple_coder::GrammarTupleCoder, error::GrammarError, native_repair};
+
+    use super::{Grammar, RuleStats, TextOptions, RULE_OFFSET};
 
     fn setup() -> Grammar {
         Grammar::from_parts(
@@ -210,8 +1100,9 @@ mod test {
     fn renumber_test() {
         let mut gr = setup();
 
-        gr.renumber();
+        let renumbering = gr.renumber();
 
+        assert_eq!(vec![3, 0, 2, 1], renumbering, "Renumbering permutation incorrect");
         assert_eq!(
             &Grammar::from_parts(
                 vec![
@@ -240,7 +1131,9 @@ mod test {
         assert_eq!(
             Grammar {
                 rules: vec![],
-                start_rule: 0
+                start_rule: 0,
+                alphabet_size: RULE_OFFSET,
+                stats: OnceLock::new(),
             },
             Grammar::empty(),
             "Empty grammar not empty"
@@ -248,7 +1141,9 @@ mod test {
         assert_eq!(
             Grammar {
                 rules: vec![],
-                start_rule: 0
+                start_rule: 0,
+                alphabet_size: RULE_OFFSET,
+                stats: OnceLock::new(),
             },
             Grammar::from_parts(vec![], 0),
             "Empty grammar produced by from_parts not empty"
@@ -256,6 +1151,7 @@ mod test {
     }
 
     #[test]
+    #[allow(deprecated, clippy::bool_assert_comparison)]
     fn terminal_non_terminal_test() {
         assert_eq!(
             true,
@@ -299,4 +1195,644 @@ mod test {
             "symbol 24 not classified as terminal"
         );
     }
+
+    #[test]
+    fn alphabet_terminal_non_terminal_test() {
+        let gr = Grammar::empty();
+        assert!(
+            gr.is_nonterminal_symbol(260),
+            "symbol 260 not classified as non-terminal"
+        );
+        assert!(
+            gr.is_nonterminal_symbol(256),
+            "symbol 256 not classified as non-terminal"
+        );
+        assert!(
+            !gr.is_nonterminal_symbol(255),
+            "symbol 255 classified as non-terminal"
+        );
+        assert!(
+            !gr.is_nonterminal_symbol(24),
+            "symbol 224 classified as non-terminal"
+        );
+
+        assert!(
+            !gr.is_terminal_symbol(260),
+            "symbol 260 classified as terminal"
+        );
+        assert!(
+            !gr.is_terminal_symbol(256),
+            "symbol 256 classified as terminal"
+        );
+        assert!(
+            gr.is_terminal_symbol(255),
+            "symbol 255 not classified as terminal"
+        );
+        assert!(
+            gr.is_terminal_symbol(24),
+            "symbol 24 not classified as terminal"
+        );
+
+        let gr = Grammar::from_parts_with_alphabet(vec![], 0, 1000);
+        assert!(gr.is_terminal_symbol(256), "symbol 256 not classified as terminal of 1000");
+        assert!(!gr.is_nonterminal_symbol(999), "symbol 999 classified as non-terminal of 1000");
+        assert!(gr.is_nonterminal_symbol(1000), "symbol 1000 not classified as non-terminal of 1000");
+    }
+
+    #[test]
+    fn integer_alphabet_test() {
+        // Terminals 0..1000, so rule 0 is 1000 and rule 1 is 1001
+        let gr = Grammar::from_parts_with_alphabet(
+            vec![vec![999, 300], vec![1000, 1000, 5], vec![1001, 256, 1000]],
+            2,
+            1000,
+        );
+
+        assert!(gr.is_terminal_symbol(999), "symbol 999 not classified as terminal");
+        assert!(gr.is_nonterminal_symbol(1000), "symbol 1000 not classified as non-terminal");
+        assert_eq!(
+            vec![999, 300, 999, 300, 5, 256, 999, 300],
+            gr.produce_source_symbols(),
+            "Source symbols not correctly reproduced"
+        );
+    }
+
+    #[test]
+    fn symbol_rule_conversion_test() {
+        let gr = Grammar::from_parts_with_alphabet(vec![], 0, 0x110000);
+        assert_eq!(0x110003, gr.nonterminal(3), "Symbol of rule 3 incorrect");
+        assert_eq!(Some(3), gr.rule_index(0x110003), "Rule of symbol 0x110003 incorrect");
+        assert_eq!(None, gr.rule_index(0x10FFFF), "Terminal 0x10FFFF classified as rule");
+        assert_eq!(None, gr.rule_index(256), "Terminal 256 classified as rule");
+    }
+
+    #[test]
+    fn non_byte_source_string_test() {
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![97, 300]], 0, 1000);
+        let mut out = vec![];
+        let written = gr.write_source_string(&mut out);
+        assert_eq!(
+            Some(std::io::ErrorKind::InvalidData),
+            written.err().map(|err| err.kind()),
+            "Terminal 300 written as a byte"
+        );
+    }
+
+    #[test]
+    fn integer_alphabet_renumber_test() {
+        let mut gr = Grammar::from_parts_with_alphabet(vec![vec![1001, 1001], vec![7, 999]], 0, 1000);
+        gr.renumber();
+        assert_eq!(
+            Grammar::from_parts_with_alphabet(vec![vec![7, 999], vec![1000, 1000]], 1, 1000),
+            gr,
+            "Grammar renumbering incorrect"
+        );
+    }
+
+    #[test]
+    fn char_at_test() {
+        let gr = setup();
+        let text = b"abcdefghabcd";
+        for (i, &c) in text.iter().enumerate() {
+            assert_eq!(Some(c as usize), gr.char_at(i), "Character {i} incorrect");
+        }
+        assert_eq!(None, gr.char_at(text.len()), "Character past the end found");
+        assert_eq!(None, Grammar::empty().char_at(0), "Character in empty grammar found");
+    }
+
+    #[test]
+    fn extract_test() {
+        let gr = setup();
+        let text = b"abcdefghabcd".map(|c| c as usize);
+        for start in 0..=text.len() {
+            for end in start..=text.len() {
+                assert_eq!(text[start..end], gr.extract(start..end), "Range {start}..{end} incorrect");
+            }
+        }
+        assert_eq!(text[7..], gr.extract(7..100), "Range past the end not cut off");
+        assert_eq!(text[..=3], gr.extract(..=3), "Inclusive range incorrect");
+        assert_eq!(text[..], gr.extract(..), "Full range incorrect");
+        assert!(gr.extract(20..30).is_empty(), "Range after the end not empty");
+    }
+
+    #[test]
+    fn extract_empty_rules_test() {
+        // Rule 1 is empty, so it must be skipped when descending
+        let gr = Grammar::from_parts(vec![vec![257, 97, 257, 258], vec![], vec![257, 98, 99]], 0);
+        assert_eq!(vec![97, 98, 99], gr.extract(..), "Grammar with empty rules extracted incorrectly");
+        assert_eq!(Some(98), gr.char_at(1), "Character after empty rule incorrect");
+    }
+
+    #[test]
+    fn random_access_invalidation_test() {
+        let mut gr = setup();
+        assert_eq!(Some(b'a' as usize), gr.char_at(0), "First character incorrect");
+        gr.set_start_rule(2);
+        assert_eq!(Some(b'd' as usize), gr.char_at(0), "Stale expansion lengths used");
+
+        let mut gr = setup();
+        assert_eq!(Some(b'd' as usize), gr.char_at(3), "Fourth character incorrect");
+        gr.renumber();
+        assert_eq!(
+            b"abcdefghabcd".map(|c| c as usize).to_vec(),
+            gr.extract(..),
+            "Stale expansion lengths used after renumbering"
+        );
+    }
+
+    #[test]
+    fn random_access_huge_text_test() {
+        // Rule i expands to 2^(i+1) a's, so the text is far longer than usize::MAX
+        let mut rules = vec![vec![97, 97]];
+        rules.extend((1..80).map(|i| vec![RULE_OFFSET + i - 1, RULE_OFFSET + i - 1]));
+        let gr = Grammar::from_parts(rules, 79);
+        assert_eq!(Some(97), gr.char_at(usize::MAX - 1), "Character far into the text incorrect");
+        assert_eq!(vec![97; 3], gr.extract(1 << 62..(1 << 62) + 3), "Range far into the text incorrect");
+    }
+
+    #[test]
+    fn rule_stats_test() {
+        let gr = setup();
+        assert_eq!(
+            [
+                RuleStats { expansion_len: 12, height: 4, references: 0 },
+                RuleStats { expansion_len: 3, height: 1, references: 2 },
+                RuleStats { expansion_len: 8, height: 3, references: 1 },
+                RuleStats { expansion_len: 6, height: 2, references: 1 },
+            ],
+            gr.rule_stats(),
+            "Rule statistics incorrect"
+        );
+        assert_eq!(6, gr.expansion_len(3), "Expansion length incorrect");
+        assert_eq!(4, gr.height(), "Height incorrect");
+        assert_eq!(12, gr.text_len(), "Text length incorrect");
+    }
+
+    #[test]
+    fn rule_stats_empty_test() {
+        let gr = Grammar::empty();
+        assert!(gr.rule_stats().is_empty(), "Statistics of rules in an empty grammar");
+        assert_eq!(0, gr.height(), "Height of empty grammar not 0");
+        assert_eq!(0, gr.text_len(), "Text length of empty grammar not 0");
+
+        let gr = Grammar::from_parts(vec![vec![]], 0);
+        assert_eq!(1, gr.height(), "Height of empty start rule not 1");
+        assert_eq!(0, gr.text_len(), "Text length of empty start rule not 0");
+    }
+
+    #[test]
+    fn deep_grammar_test() {
+        // Rule i expands to a followed by i b's, so the start rule has a derivation of height DEPTH
+        const DEPTH: usize = 300_000;
+        let mut rules = vec![vec![97]];
+        rules.extend((1..DEPTH).map(|i| vec![RULE_OFFSET + i - 1, 98]));
+        let mut gr = Grammar::from_parts(rules, DEPTH - 1);
+        let text = std::iter::once('a').chain(std::iter::repeat_n('b', DEPTH - 1)).collect::<String>();
+
+        assert_eq!(DEPTH, gr.height(), "Height of deep grammar incorrect");
+        assert_eq!(DEPTH, gr.text_len(), "Text length of deep grammar incorrect");
+        assert_eq!(Some(97), gr.char_at(0), "First character of deep grammar incorrect");
+        assert_eq!(vec![98; 3], gr.extract(DEPTH - 3..), "End of deep grammar incorrect");
+
+        let original = gr.clone();
+        gr.renumber();
+        assert_eq!(original, gr, "Renumbering of deep grammar incorrect");
+        assert_eq!(Ok(text), gr.produce_source_string(), "Deep grammar producing the wrong string");
+    }
+
+    #[test]
+    fn bytes_test() {
+        let gr = setup();
+        assert_eq!(
+            b"abcdefghabcd".to_vec(),
+            gr.bytes().collect::<Vec<_>>(),
+            "Bytes not correctly produced"
+        );
+        assert_eq!(b"abc".to_vec(), gr.bytes().take(3).collect::<Vec<_>>(), "Prefix not correctly produced");
+        assert_eq!(None, Grammar::empty().bytes().next(), "Bytes produced by empty grammar");
+    }
+
+    #[test]
+    fn reader_test() {
+        let gr = setup();
+        let mut reader = gr.reader();
+        let mut buf = [0u8; 5];
+        let mut out = vec![];
+        loop {
+            let read = reader.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..read]);
+        }
+        assert_eq!(b"abcdefghabcd".to_vec(), out, "Text not correctly read");
+    }
+
+    #[test]
+    fn reader_non_byte_test() {
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![97, 98, 300, 99]], 0, 1000);
+        let mut reader = gr.reader();
+        let mut buf = [0u8; 8];
+        assert_eq!(2, reader.read(&mut buf).unwrap(), "Bytes before the terminal not read");
+        assert_eq!(b"ab", &buf[..2], "Bytes before the terminal incorrect");
+        assert_eq!(
+            Some(std::io::ErrorKind::InvalidData),
+            reader.read(&mut buf).err().map(|err| err.kind()),
+            "Terminal 300 read as a byte"
+        );
+    }
+
+    #[test]
+    fn borrowing_expansion_test() {
+        let gr = setup();
+        let original = gr.clone();
+
+        let mut out = vec![];
+        gr.write_source_string(&mut out).unwrap();
+        assert_eq!(b"abcdefghabcd".to_vec(), out, "Source string not correctly written");
+        assert_eq!(Ok("abcdefghabcd".to_owned()), gr.produce_source_string(), "Source string not correctly reproduced");
+        assert_eq!(12, gr.produce_source_symbols().len(), "Source symbols not correctly reproduced");
+        assert_eq!(original.consume(), gr.consume(), "Expansion changed the rules");
+    }
+
+    #[test]
+    fn renumber_permutation_test() {
+        let mut gr = setup();
+        let original = gr.clone();
+        let renumbering = gr.renumber();
+
+        for (old_id, rule) in original.iter().enumerate() {
+            let renumbered = rule
+                .iter()
+                .map(|&symbol| match original.rule_index(symbol) {
+                    Some(id) => gr.nonterminal(renumbering[id]),
+                    None => symbol,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(renumbered, gr[renumbering[old_id]], "Rule {old_id} not mapped by the permutation");
+        }
+        assert!(Grammar::empty().renumber().is_empty(), "Permutation of empty grammar not empty");
+    }
+
+    #[test]
+    fn validate_test() {
+        assert_eq!(Ok(()), setup().validate(), "Valid grammar rejected");
+        assert_eq!(Ok(()), Grammar::empty().validate(), "Empty grammar rejected");
+        assert_eq!(Ok(()), Grammar::from_parts(vec![vec![]], 0).validate(), "Empty start rule rejected");
+
+        let cases = [
+            (
+                Grammar::from_parts(vec![vec![97]], 1),
+                GrammarError::StartRuleOutOfRange { start_rule: 1, rule_count: 1 },
+            ),
+            (
+                Grammar::from_parts(vec![vec![97, 257], vec![98, 260]], 0),
+                GrammarError::DanglingReference { rule: 1, symbol: 260 },
+            ),
+            (
+                Grammar::from_parts(vec![vec![97, 257], vec![]], 0),
+                GrammarError::EmptyRule(1),
+            ),
+            (
+                Grammar::from_parts(vec![vec![257], vec![98, 258], vec![257, 99]], 0),
+                GrammarError::Cycle(1),
+            ),
+            (
+                Grammar::from_parts(vec![vec![256]], 0),
+                GrammarError::Cycle(0),
+            ),
+            (
+                Grammar::from_parts(vec![vec![97, 98], vec![99], vec![256, 256]], 2),
+                GrammarError::UnreachableRule(1),
+            ),
+            (
+                // The unreachable rules form a cycle, which is the worse problem
+                Grammar::from_parts(vec![vec![97], vec![258], vec![257]], 0),
+                GrammarError::Cycle(1),
+            ),
+        ];
+        for (gr, err) in cases {
+            assert_eq!(Err(err.clone()), gr.validate(), "Invalid grammar not rejected with {err}");
+        }
+    }
+
+    #[test]
+    fn renumber_unreachable_test() {
+        // Rule 0 is unreachable from rule 2
+        let mut gr = setup();
+        gr.set_start_rule(2);
+        let renumbering = gr.renumber();
+
+        assert_eq!(vec![2, 0, 3, 1], renumbering, "Renumbering permutation incorrect");
+        assert_eq!(
+            Grammar::from_parts(
+                vec![
+                    vec![97, 98, 99],
+                    vec![102, 103, 104, 256],
+                    vec![256, 259, 100],
+                    vec![100, 101, 257],
+                ],
+                3
+            ),
+            gr,
+            "Grammar with unreachable rule renumbered incorrectly"
+        );
+        assert_eq!(Ok("defghabc".to_owned()), gr.produce_source_string(), "Renumbering changed the text");
+    }
+
+    #[test]
+    fn prune_test() {
+        let mut gr = setup();
+        gr.set_start_rule(2);
+        let mapping = gr.prune();
+
+        assert_eq!(vec![None, Some(0), Some(1), Some(2)], mapping, "Pruning mapping incorrect");
+        assert_eq!(
+            Grammar::from_parts(vec![vec![97, 98, 99], vec![100, 101, 258], vec![102, 103, 104, 256]], 1),
+            gr,
+            "Grammar pruned incorrectly"
+        );
+        assert_eq!(Ok(()), gr.validate(), "Pruned grammar invalid");
+        assert_eq!(Ok("defghabc".to_owned()), gr.produce_source_string(), "Pruning changed the text");
+
+        let mut gr = setup();
+        assert_eq!(vec![Some(0), Some(1), Some(2), Some(3)], gr.prune(), "Reachable rule pruned");
+        assert_eq!(setup(), gr, "Grammar without unreachable rules changed");
+        assert!(Grammar::empty().prune().is_empty(), "Mapping of empty grammar not empty");
+    }
+
+    #[test]
+    fn inline_test() {
+        // Rules 2 and 3 are only referenced once
+        let mut gr = setup();
+        assert_eq!(2, gr.inline::<GrammarTupleCoder>(0), "Wrong number of rules inlined");
+        assert_eq!(
+            Grammar::from_parts(vec![vec![257, 100, 101, 102, 103, 104, 257, 100], vec![97, 98, 99]], 0),
+            gr,
+            "Single-use rules inlined incorrectly"
+        );
+
+        // Inlining rule 1 as well saves its length and the 32-bit references to it
+        let mut gr = setup();
+        assert_eq!(3, gr.inline::<GrammarTupleCoder>(usize::MAX), "Wrong number of rules inlined");
+        assert_eq!(
+            Grammar::from_parts(vec![b"abcdefghabcd".iter().map(|&c| c as usize).collect()], 0),
+            gr,
+            "Short rules inlined incorrectly"
+        );
+    }
+
+    #[test]
+    fn inline_keeps_text_test() {
+        let text = b"how much wood would a woodchuck chuck if a woodchuck could chuck wood".repeat(20);
+        for max_len in [0, 2, 8, usize::MAX] {
+            let mut gr = native_repair::repair_bytes(&text);
+            let rule_count = gr.rule_count();
+            let inlined = gr.inline::<GrammarTupleCoder>(max_len);
+            assert_eq!(rule_count - inlined, gr.rule_count(), "Inlined rules not removed");
+            assert_eq!(Ok(()), gr.validate(), "Inlining produced an invalid grammar");
+            assert_eq!(text, gr.bytes().collect::<Vec<_>>(), "Inlining changed the text");
+        }
+        assert_eq!(0, Grammar::empty().inline::<GrammarTupleCoder>(usize::MAX), "Rules inlined in empty grammar");
+    }
+
+    #[test]
+    fn to_slp_test() {
+        let gr = setup();
+        assert!(!gr.is_slp(), "Grammar with rules of three symbols is an SLP");
+        for balanced in [false, true] {
+            let slp = gr.to_slp(balanced).unwrap();
+            assert!(slp.is_slp(), "Converted grammar is no SLP: {slp:?}");
+            assert_eq!(Ok(()), slp.validate(), "Converted grammar invalid");
+            assert_eq!(gr.produce_source_string(), slp.produce_source_string(), "Conversion changed the text");
+        }
+
+        let gr = Grammar::from_parts(vec![(97..105).collect()], 0);
+        assert_eq!(
+            Grammar::from_parts(
+                vec![
+                    vec![261, 262],
+                    vec![97, 98],
+                    vec![99, 100],
+                    vec![101, 102],
+                    vec![103, 104],
+                    vec![257, 258],
+                    vec![259, 260],
+                ],
+                0
+            ),
+            gr.to_slp(true).unwrap(),
+            "Rule not split into a balanced tree"
+        );
+        assert_eq!(
+            Grammar::from_parts(
+                vec![vec![262, 104], vec![97, 98], vec![257, 99], vec![258, 100], vec![259, 101], vec![260, 102], vec![261, 103]],
+                0
+            ),
+            gr.to_slp(false).unwrap(),
+            "Rule not split into a left-deep chain"
+        );
+    }
+
+    #[test]
+    fn to_slp_short_rules_test() {
+        // Rule 1 is a single terminal and rule 0 is the start rule's only symbol
+        let gr = Grammar::from_parts(vec![vec![257, 257], vec![97], vec![256]], 2);
+        let slp = gr.to_slp(true).unwrap();
+        assert_eq!(Grammar::from_parts(vec![vec![97, 97]], 0), slp, "Short rules not inlined");
+
+        for text in [&b""[..], b"a"] {
+            let gr = Grammar::from_parts(vec![text.iter().map(|&c| c as usize).collect()], 0);
+            assert!(gr.is_slp(), "Grammar of a text shorter than 2 is no SLP");
+            assert_eq!(gr, gr.to_slp(false).unwrap(), "Grammar of a text shorter than 2 changed");
+        }
+        let gr = Grammar::from_parts(vec![vec![257], vec![97, 98]], 0);
+        assert!(!gr.is_slp(), "Start rule of a single rule is an SLP");
+        assert_eq!(
+            Grammar::from_parts(vec![vec![97, 98]], 0),
+            gr.to_slp(false).unwrap(),
+            "Start rule of a single rule not replaced"
+        );
+        assert_eq!(
+            Err(GrammarError::Cycle(0)),
+            Grammar::from_parts(vec![vec![256, 97]], 0).to_slp(true),
+            "Invalid grammar converted"
+        );
+    }
+
+    #[test]
+    fn to_slp_repair_test() {
+        // Little repetition, so the start rule is long
+        let text = (0..2000usize).map(|i| (i * i % 251) as u8).collect::<Vec<_>>();
+        let gr = native_repair::repair_bytes(&text);
+        let unbalanced = gr.to_slp(false).unwrap();
+        let balanced = gr.to_slp(true).unwrap();
+        for slp in [&unbalanced, &balanced] {
+            assert!(slp.is_slp(), "Converted RePair grammar is no SLP");
+            assert_eq!(text, slp.bytes().collect::<Vec<_>>(), "Conversion changed the text");
+        }
+        assert!(balanced.height() < unbalanced.height(), "Balanced conversion not lower");
+    }
+
+    #[test]
+    fn to_pair_rules_test() {
+        let gr = Grammar::from_parts(vec![vec![257, 257, 97], vec![98, 99, 100]], 0);
+        let pairs = gr.to_pair_rules().unwrap();
+        assert_eq!(
+            &[257, 257, 97][..],
+            &pairs.rules()[pairs.start_rule()][..],
+            "Start rule not kept"
+        );
+        assert!(
+            pairs
+                .rules()
+                .iter()
+                .enumerate()
+                .all(|(i, rule)| i == pairs.start_rule() || rule.len() == 2),
+            "Rule other than the start rule is no pair"
+        );
+        assert_eq!(
+            gr.bytes().collect::<Vec<_>>(),
+            pairs.bytes().collect::<Vec<_>>(),
+            "Conversion changed the text"
+        );
+    }
+
+    /// Whether the heights of the two symbols of each rule differ by at most one
+    fn is_avl(gr: &Grammar) -> bool {
+        let height = |symbol: usize| gr.rule_index(symbol).map_or(0, |rule| gr.rule_stats()[rule].height);
+        gr.rules()
+            .iter()
+            .all(|rule| rule.len() < 2 || height(rule[0]).abs_diff(height(rule[1])) <= 1)
+    }
+
+    #[test]
+    fn rebalance_test() {
+        // Rule i expands to a followed by i b's, like in the deep grammar test
+        const DEPTH: usize = 300_000;
+        let mut rules = vec![vec![97]];
+        rules.extend((1..DEPTH).map(|i| vec![RULE_OFFSET + i - 1, 98]));
+        let mut gr = Grammar::from_parts(rules, DEPTH - 1);
+        let text = gr.bytes().collect::<Vec<_>>();
+
+        let report = gr.rebalance().unwrap();
+        assert_eq!(DEPTH, report.height_before, "Height before rebalancing incorrect");
+        assert_eq!(DEPTH, report.rules_before, "Rule count before rebalancing incorrect");
+        assert_eq!(gr.height(), report.height_after, "Height after rebalancing incorrect");
+        assert_eq!(gr.rule_count(), report.rules_after, "Rule count after rebalancing incorrect");
+        assert!(
+            report.height_after as f64 <= 1.45 * (DEPTH as f64).log2() + 2.0,
+            "Rebalanced height {} not logarithmic",
+            report.height_after
+        );
+        assert!(gr.is_slp(), "Rebalanced grammar is no SLP");
+        assert!(is_avl(&gr), "Rebalanced grammar is not balanced");
+        assert_eq!(Ok(()), gr.validate(), "Rebalanced grammar invalid");
+        assert_eq!(text, gr.bytes().collect::<Vec<_>>(), "Rebalancing changed the text");
+    }
+
+    #[test]
+    fn rebalance_repair_test() {
+        let text = (0..5000usize).map(|i| b'a' + (i % 7 * i % 5) as u8).collect::<Vec<_>>();
+        let mut gr = native_repair::repair_bytes(&text).to_slp(false).unwrap();
+        let height_before = gr.height();
+
+        let report = gr.rebalance().unwrap();
+        assert_eq!(height_before, report.height_before, "Height before rebalancing incorrect");
+        assert!(report.height_after < report.height_before, "Rebalancing did not lower the grammar");
+        assert!(is_avl(&gr), "Rebalanced grammar is not balanced");
+        assert_eq!(text, gr.bytes().collect::<Vec<_>>(), "Rebalancing changed the text");
+
+        let mut balanced = gr.clone();
+        balanced.rebalance().unwrap();
+        assert_eq!(gr, balanced, "Rebalancing a balanced grammar changed it");
+    }
+
+    #[test]
+    fn rebalance_short_text_test() {
+        let mut gr = setup();
+        gr.rebalance().unwrap();
+        assert!(is_avl(&gr), "Rebalanced grammar is not balanced");
+        assert_eq!(
+            Ok("abcdefghabcd".to_owned()),
+            gr.produce_source_string(),
+            "Rebalancing changed the text"
+        );
+
+        for rules in [vec![vec![97]], vec![vec![]], vec![vec![257], vec![97]]] {
+            let mut gr = Grammar::from_parts(rules, 0);
+            let text = gr.produce_source_symbols();
+            gr.rebalance().unwrap();
+            assert!(gr.is_slp(), "Rebalanced short grammar is no SLP");
+            assert_eq!(text, gr.produce_source_symbols(), "Rebalancing changed the short text");
+        }
+
+        let mut gr = Grammar::empty();
+        let report = gr.rebalance().unwrap();
+        assert_eq!(0, report.height_after, "Empty grammar has a height");
+        assert_eq!(Grammar::empty(), gr, "Rebalancing changed the empty grammar");
+
+        let mut gr = Grammar::from_parts(vec![vec![256, 97]], 0);
+        assert_eq!(Err(GrammarError::Cycle(0)), gr.rebalance(), "Invalid grammar rebalanced");
+        assert_eq!(Grammar::from_parts(vec![vec![256, 97]], 0), gr, "Invalid grammar changed");
+    }
+
+    #[test]
+    fn display_test() {
+        let gr = setup();
+        let expected = "R0 -> R1 R2 'd'\nR1 -> 'a' 'b' 'c'\nR2 -> 'd' 'e' R3\nR3 -> 'f' 'g' 'h' R1\n";
+        assert_eq!(expected, gr.to_string(), "Grammar displayed incorrectly");
+
+        let mut out = vec![];
+        gr.write_text(&mut out, &TextOptions::default()).unwrap();
+        assert_eq!(expected.as_bytes(), out, "Text without annotations differs from display");
+
+        let options = TextOptions {
+            expansion_len: true,
+            preview: Some(5),
+        };
+        let mut out = vec![];
+        gr.write_text(&mut out, &options).unwrap();
+        let expected = "R0 -> R1 R2 'd' # len 12 \"abcde\"...\n\
+            R1 -> 'a' 'b' 'c' # len 3 \"abc\"\n\
+            R2 -> 'd' 'e' R3 # len 8 \"defgh\"...\n\
+            R3 -> 'f' 'g' 'h' R1 # len 6 \"fghab\"...\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap(), "Annotated text incorrect");
+    }
+
+    #[test]
+    fn display_escape_test() {
+        let gr = Grammar::from_parts(vec![vec![10, 39, 92, 0, 200, 34, 32]], 0);
+        assert_eq!(
+            "R0 -> '\\n' '\\'' '\\\\' '\\x00' '\\xc8' '\"' ' '\n",
+            gr.to_string(),
+            "Terminals escaped incorrectly"
+        );
+
+        let options = TextOptions {
+            expansion_len: false,
+            preview: Some(10),
+        };
+        let mut out = vec![];
+        gr.write_text(&mut out, &options).unwrap();
+        assert_eq!(
+            "R0 -> '\\n' '\\'' '\\\\' '\\x00' '\\xc8' '\"' ' ' # \"\\n'\\\\\\x00\\xc8\\\" \"\n",
+            String::from_utf8(out).unwrap(),
+            "Preview escaped incorrectly"
+        );
+
+        let gr = Grammar::from_parts_with_alphabet(vec![vec![1001, 5], vec![7, 8]], 0, 1000);
+        assert_eq!("R0 -> R1 5\nR1 -> 7 8\n", gr.to_string(), "Integer terminals displayed incorrectly");
+        let options = TextOptions {
+            expansion_len: true,
+            preview: Some(2),
+        };
+        let mut out = vec![];
+        gr.write_text(&mut out, &options).unwrap();
+        assert_eq!(
+            "R0 -> R1 5 # len 3 [7 8]...\nR1 -> 7 8 # len 2 [7 8]\n",
+            String::from_utf8(out).unwrap(),
+            "Integer preview incorrect"
+        );
+    }
 }
@@ -3,9 +3,14 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+pub mod backend;
 pub mod coding;
 pub mod error;
 pub mod grammar;
+pub mod native_repair;
+#[cfg(feature = "navarro")]
 mod bindings;
 
-pub use bindings::repair;
+pub use backend::{Backend, RePairOptions};
+#[cfg(feature = "navarro")]
+pub use bindings::{repair, repair_bytes, repair_ints, repair_ints_with_options, repair_with_options};
@@ -1,10 +1,9 @@
+use std::io::Write;
+
 use clap::Parser;
-use rreader::coding::{
-    grammar_coder::{GrammarDecoder, GrammarEncoder},
-    grammar_tuple_coder::GrammarTupleCoder,
-    navarro_repair_decoder::NavarroRepairDecoder,
-};
+use rreader::coding::{grammar_ans_coder::GrammarAnsCoder, Format};
 use rreader::error::RReaderError;
+use rreader::{Backend, RePairOptions};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -15,24 +14,82 @@ struct Args {
     decompress: bool,
     #[clap(short, long, help = "The output file")]
     out: Option<String>,
+    #[clap(short, long, default_value_t = Backend::default(), help = "The RePair implementation to use (navarro or native)")]
+    backend: Backend,
+    #[clap(
+        long,
+        default_value_t = RePairOptions::default().factor,
+        help = "RePair's space/time trade-off between 0 and 1. Closer to 1 uses less memory but is slower"
+    )]
+    factor: f32,
+    #[clap(
+        long,
+        default_value_t = RePairOptions::default().minsize,
+        help = "The minimum size of the C RePair's growable arrays"
+    )]
+    minsize: usize,
+    #[clap(
+        short,
+        long,
+        help = "Treat the uncompressed data as a sequence of 32-bit little-endian integers instead of bytes"
+    )]
+    integers: bool,
+    #[clap(long, default_value_t = Format::default(), help = "The format of the compressed file (tuple, packed, huffman, ans or text)")]
+    format: Format,
+    #[clap(long, conflicts_with = "decompress", help = "Print the size of the grammar in the ans and tuple formats")]
+    compare: bool,
+    #[clap(
+        long,
+        value_name = "MAX_LEN",
+        conflicts_with = "decompress",
+        help = "Inline rules that are cheaper to repeat in the chosen format. Rules referenced more than once are only inlined if they have at most MAX_LEN symbols"
+    )]
+    inline: Option<usize>,
 }
 
-fn main() -> Result<(), RReaderError> {
-    let args = Args::parse();
+fn main() {
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    }
+}
 
+fn run(args: Args) -> Result<(), RReaderError> {
     if !args.decompress {
-        let repair_result = rreader::repair(&args.file)?;
-        let grammar = NavarroRepairDecoder::decode(repair_result)?;
+        let options = RePairOptions {
+            factor: args.factor,
+            minsize: args.minsize,
+        };
+        let mut grammar = if args.integers {
+            args.backend.repair_int_file(&args.file, &options)?
+        } else {
+            args.backend.repair_file(&args.file, &options)?
+        };
+        if let Some(max_len) = args.inline {
+            args.format.inline(&mut grammar, max_len);
+        }
+        if args.compare {
+            eprintln!("{}", GrammarAnsCoder::compare_with_tuple(&grammar)?);
+        }
         let out_file_name = args.out.unwrap_or(format!("{}.grm", &args.file));
         let out_file = std::fs::File::create(out_file_name)?;
 
-        GrammarTupleCoder::encode(grammar, out_file)?
+        args.format.encode(grammar, std::io::BufWriter::new(out_file))?
     } else {
         let file = std::fs::File::open(&args.file)?;
         // out is required when decompressing
         let out_file = std::fs::File::create(args.out.unwrap())?;
-        let grammar = GrammarTupleCoder::decode(file)?;
-        grammar.write_source_string(out_file)?
+        let grammar = args.format.decode(std::io::BufReader::new(file))?;
+        if args.integers {
+            let mut out = std::io::BufWriter::new(out_file);
+            for symbol in grammar.symbols() {
+                let symbol = u32::try_from(symbol).map_err(|_| RReaderError::TooLarge("Symbol"))?;
+                out.write_all(&symbol.to_le_bytes())?;
+            }
+            out.flush()?
+        } else {
+            grammar.write_source_string(out_file)?
+        }
     }
 
     Ok(())
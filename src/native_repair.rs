@@ -0,0 +1,402 @@
+//! A RePair implementation in pure Rust, following Larsson and Moffat.
+//!
+//! The sequence is kept as a doubly linked list over its positions, so replacing a pair
+//! just unlinks the position of its right symbol. Every pair that occurs in the sequence
+//! has a record with its frequency and a linked list of the positions it occurs at.
+//! The most frequent pair is found with a priority queue over the pair frequencies.
+//! Once enough positions have been unlinked, all arrays are compacted to the live positions.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::{
+    backend::RePairOptions,
+    error::RePairError,
+    grammar::{Grammar, RULE_OFFSET},
+};
+
+const NONE: usize = usize::MAX;
+
+type Pair = (usize, usize);
+
+struct PairRecord {
+    freq: usize,
+    /// The first position in the occurrence list of this pair
+    head: usize,
+}
+
+struct RePair {
+    seq: Vec<usize>,
+    /// The number of live positions in the sequence
+    len: usize,
+    /// Next and previous live position in the sequence
+    next: Vec<usize>,
+    prev: Vec<usize>,
+    /// Next and previous occurrence of the pair starting at a position
+    occ_next: Vec<usize>,
+    occ_prev: Vec<usize>,
+    /// Whether the pair starting at a position is in its occurrence list
+    listed: Vec<bool>,
+    pairs: HashMap<Pair, PairRecord>,
+    /// Pairs by frequency. Entries are only added when a frequency increases,
+    /// so an entry may be stale and has to be checked against its record.
+    queue: BinaryHeap<(usize, Reverse<Pair>)>,
+}
+
+/// Runs RePair on an in-memory buffer and builds the resulting grammar directly.
+///
+/// Like the grammars produced by [`NavarroRepairDecoder`](crate::coding::navarro_repair_decoder::NavarroRepairDecoder),
+/// every rule except the start rule is a pair, the rules are numbered in the order they were created
+/// and the start rule is the last rule.
+pub fn repair_bytes(text: &[u8]) -> Grammar {
+    build(text, RePairOptions::default().factor)
+}
+
+/// Runs RePair on an in-memory buffer with the given space/time trade-offs.
+/// Only [`RePairOptions::factor`] applies to this implementation.
+pub fn repair_with_options(text: &[u8], options: &RePairOptions) -> Result<Grammar, RePairError> {
+    options.validate()?;
+    Ok(build(text, options.factor))
+}
+
+/// Runs RePair on a sequence of integers.
+/// The terminals of the grammar are the integers themselves, so its alphabet size is one more than the largest of them.
+pub fn repair_ints(symbols: &[u32]) -> Grammar {
+    build_ints(symbols, RePairOptions::default().factor)
+}
+
+/// Runs RePair on a sequence of integers with the given space/time trade-offs.
+pub fn repair_ints_with_options(symbols: &[u32], options: &RePairOptions) -> Result<Grammar, RePairError> {
+    options.validate()?;
+    Ok(build_ints(symbols, options.factor))
+}
+
+fn build(text: &[u8], factor: f32) -> Grammar {
+    let symbols = text.iter().map(|&c| c as usize).collect();
+    build_symbols(symbols, RULE_OFFSET, factor)
+}
+
+fn build_ints(symbols: &[u32], factor: f32) -> Grammar {
+    let alphabet_size = symbols.iter().map(|&s| s as usize + 1).max().unwrap_or(0);
+    let symbols = symbols.iter().map(|&s| s as usize).collect();
+    build_symbols(symbols, alphabet_size, factor)
+}
+
+fn build_symbols(symbols: Vec<usize>, alphabet_size: usize, factor: f32) -> Grammar {
+    let rules = RePair::new(symbols).run(alphabet_size, factor);
+    let start_rule = rules.len() - 1;
+    Grammar::from_parts_with_alphabet(rules, start_rule, alphabet_size)
+}
+
+impl RePair {
+    fn new(seq: Vec<usize>) -> Self {
+        let n = seq.len();
+        let mut repair = RePair {
+            next: (1..=n).map(|i| if i < n { i } else { NONE }).collect(),
+            prev: (0..n).map(|i| if i > 0 { i - 1 } else { NONE }).collect(),
+            occ_next: vec![NONE; n],
+            occ_prev: vec![NONE; n],
+            listed: vec![false; n],
+            pairs: HashMap::new(),
+            queue: BinaryHeap::new(),
+            len: n,
+            seq,
+        };
+        for i in 0..n.saturating_sub(1) {
+            repair.add_occurrence(i);
+        }
+        repair
+    }
+
+    /// Replaces pairs until no pair occurs twice.
+    /// Returns the rules in order of creation followed by the final sequence.
+    /// Rule ids start at `first_rule`, so all symbols in the sequence must be smaller than it.
+    /// The arrays are compacted whenever fewer than `factor` of their positions are live.
+    fn run(mut self, first_rule: usize, factor: f32) -> Vec<Vec<usize>> {
+        let mut rules = vec![];
+
+        while let Some(pair) = self.most_frequent() {
+            let symbol = first_rule + rules.len();
+            rules.push(vec![pair.0, pair.1]);
+            self.replace(pair, symbol);
+            if (self.len as f64) < factor as f64 * self.seq.len() as f64 {
+                self.compact();
+            }
+        }
+
+        let mut sequence = vec![];
+        let mut pos = if self.seq.is_empty() { NONE } else { 0 };
+        while pos != NONE {
+            sequence.push(self.seq[pos]);
+            pos = self.next[pos];
+        }
+        rules.push(sequence);
+
+        rules
+    }
+
+    fn pair_at(&self, pos: usize) -> Pair {
+        (self.seq[pos], self.seq[self.next[pos]])
+    }
+
+    /// Pops pairs from the queue until one is found whose entry is up to date
+    fn most_frequent(&mut self) -> Option<Pair> {
+        while let Some((freq, Reverse(pair))) = self.queue.pop() {
+            let current = match self.pairs.get(&pair) {
+                Some(record) => record.freq,
+                None => continue,
+            };
+            if current == freq {
+                return Some(pair);
+            }
+            // The frequency decreased since this entry was added
+            if current < freq && current >= 2 {
+                self.queue.push((current, Reverse(pair)));
+            }
+        }
+        None
+    }
+
+    /// Inserts the pair starting at pos into its occurrence list.
+    /// A pair of two equal symbols is not counted if it overlaps with a counted occurrence.
+    fn add_occurrence(&mut self, pos: usize) {
+        let pair = self.pair_at(pos);
+        if pair.0 == pair.1 {
+            let prev = self.prev[pos];
+            let next = self.next[pos];
+            if (prev != NONE && self.listed[prev] && self.pair_at(prev) == pair)
+                || (next != NONE && self.listed[next] && self.pair_at(next) == pair)
+            {
+                return;
+            }
+        }
+
+        let record = self.pairs.entry(pair).or_insert(PairRecord { freq: 0, head: NONE });
+        record.freq += 1;
+        self.occ_prev[pos] = NONE;
+        self.occ_next[pos] = record.head;
+        if record.head != NONE {
+            self.occ_prev[record.head] = pos;
+        }
+        record.head = pos;
+        self.listed[pos] = true;
+
+        if record.freq >= 2 {
+            self.queue.push((record.freq, Reverse(pair)));
+        }
+    }
+
+    /// Removes the pair starting at pos from its occurrence list, if it is in there
+    fn remove_occurrence(&mut self, pos: usize) {
+        if !self.listed[pos] {
+            return;
+        }
+        let pair = self.pair_at(pos);
+        let (occ_prev, occ_next) = (self.occ_prev[pos], self.occ_next[pos]);
+        if occ_next != NONE {
+            self.occ_prev[occ_next] = occ_prev;
+        }
+        if occ_prev != NONE {
+            self.occ_next[occ_prev] = occ_next;
+        }
+        self.listed[pos] = false;
+
+        let record = self.pairs.get_mut(&pair).expect("listed pair without record");
+        if occ_prev == NONE {
+            record.head = occ_next;
+        }
+        record.freq -= 1;
+        if record.freq == 0 {
+            self.pairs.remove(&pair);
+        }
+    }
+
+    /// Replaces every occurrence of pair with symbol
+    fn replace(&mut self, pair: Pair, symbol: usize) {
+        while let Some(pos) = self.pairs.get(&pair).map(|record| record.head) {
+            // Replacing bc with X in abcd
+            let right = self.next[pos];
+            let before = self.prev[pos];
+            let after = self.next[right];
+
+            self.remove_occurrence(pos);
+            if before != NONE {
+                self.remove_occurrence(before);
+            }
+            if after != NONE {
+                self.remove_occurrence(right);
+            }
+
+            self.seq[pos] = symbol;
+            self.len -= 1;
+            self.next[pos] = after;
+            if after != NONE {
+                self.prev[after] = pos;
+            }
+
+            if before != NONE {
+                self.add_occurrence(before);
+            }
+            if after != NONE {
+                self.add_occurrence(pos);
+            }
+        }
+    }
+
+    /// Moves the live positions to the front, keeping their order.
+    /// Positions are never unlinked at the front, so the sequence still starts at 0.
+    fn compact(&mut self) {
+        // prev is rebuilt from scratch anyway, so it can hold the new positions meanwhile
+        let mut pos = 0;
+        for new_pos in 0..self.len {
+            let next = self.next[pos];
+            self.prev[pos] = new_pos;
+            pos = next;
+        }
+        let new_position = |pos: usize, prev: &[usize]| if pos == NONE { NONE } else { prev[pos] };
+
+        let mut seq = Vec::with_capacity(self.len);
+        let mut occ_next = Vec::with_capacity(self.len);
+        let mut occ_prev = Vec::with_capacity(self.len);
+        let mut listed = Vec::with_capacity(self.len);
+        let mut pos = 0;
+        while pos != NONE {
+            seq.push(self.seq[pos]);
+            // The links of unlisted positions are stale and may point to removed positions, which have no new position
+            if self.listed[pos] {
+                occ_next.push(new_position(self.occ_next[pos], &self.prev));
+                occ_prev.push(new_position(self.occ_prev[pos], &self.prev));
+            } else {
+                occ_next.push(NONE);
+                occ_prev.push(NONE);
+            }
+            listed.push(self.listed[pos]);
+            pos = self.next[pos];
+        }
+        for record in self.pairs.values_mut() {
+            record.head = new_position(record.head, &self.prev);
+        }
+
+        let n = self.len;
+        self.seq = seq;
+        self.occ_next = occ_next;
+        self.occ_prev = occ_prev;
+        self.listed = listed;
+        self.next = (1..=n).map(|i| if i < n { i } else { NONE }).collect();
+        self.prev = (0..n).map(|i| if i > 0 { i - 1 } else { NONE }).collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::RePairOptions;
+
+    use super::{repair_bytes, repair_ints, repair_with_options};
+
+    fn texts() -> Vec<Vec<u8>> {
+        vec![
+            b"".to_vec(),
+            b"a".to_vec(),
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+            b"abababababababababababa".to_vec(),
+            b"abracadabra abracadabra abracadabra".to_vec(),
+            b"aceacegacga".to_vec(),
+            (0..5000usize).map(|i| (i * i % 7 + i % 3) as u8).collect(),
+            (0..=255u8).cycle().take(3000).collect(),
+            // Compacted several times, with stale occurrence links of unlisted positions in between
+            (0..50000usize).map(|i| b'a' + (i % 13 + i % 7 + i / 1000 % 3 + i * i % 5) as u8).collect(),
+        ]
+    }
+
+    #[test]
+    fn native_reproduce_test() {
+        for text in texts() {
+            let mut out = vec![];
+            let res = repair_bytes(&text).write_source_string(&mut out);
+            assert!(res.is_ok(), "Error expanding grammar: {:?}", res);
+            assert_eq!(text, out, "Grammar producing the wrong text");
+        }
+    }
+
+    #[test]
+    fn native_compaction_test() {
+        // A factor close to 1 compacts after almost every replacement
+        let options = RePairOptions { factor: 0.99, ..Default::default() };
+        for text in texts() {
+            let mut out = vec![];
+            let res = repair_with_options(&text, &options)
+                .unwrap()
+                .write_source_string(&mut out);
+            assert!(res.is_ok(), "Error expanding grammar: {:?}", res);
+            assert_eq!(text, out, "Grammar producing the wrong text after compaction");
+        }
+    }
+
+    #[test]
+    fn native_ints_test() {
+        let symbols = (0..3000u32).map(|i| (i % 17) * 100_000 + i % 5).collect::<Vec<_>>();
+        let gr = repair_ints(&symbols);
+        assert_eq!(1_600_005, gr.alphabet_size(), "Alphabet size is not the largest symbol + 1");
+        assert_eq!(
+            symbols.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+            gr.produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+
+        let gr = repair_ints(&[u32::MAX, 0, u32::MAX, 0]);
+        assert_eq!(
+            vec![u32::MAX as usize, 0, u32::MAX as usize, 0],
+            gr.produce_source_symbols(),
+            "Grammar producing the wrong symbols"
+        );
+    }
+
+    #[test]
+    fn native_random_access_test() {
+        for text in texts() {
+            let gr = repair_bytes(&text);
+            let symbols = text.iter().map(|&c| c as usize).collect::<Vec<_>>();
+            for (i, &c) in symbols.iter().enumerate().step_by(7) {
+                assert_eq!(Some(c), gr.char_at(i), "Character {i} incorrect");
+                let end = (i + 100).min(symbols.len());
+                assert_eq!(symbols[i..end], gr.extract(i..end), "Range {i}..{end} incorrect");
+            }
+            assert_eq!(None, gr.char_at(text.len()), "Character past the end found");
+        }
+    }
+
+    #[test]
+    fn native_shape_test() {
+        let gr = repair_bytes(b"abababababababababababa");
+        let (rules, start_rule) = gr.consume();
+        assert_eq!(rules.len() - 1, start_rule, "Start rule is not the last rule");
+        assert!(
+            rules[..start_rule].iter().all(|rule| rule.len() == 2),
+            "Rule that is not a pair"
+        );
+        assert!(rules[start_rule].len() < 23, "Nothing was compressed");
+    }
+
+    #[cfg(feature = "navarro")]
+    #[test]
+    fn native_navarro_differential_test() {
+        use crate::coding::{grammar_coder::GrammarDecoder, navarro_repair_decoder::NavarroRepairDecoder};
+
+        for text in texts() {
+            let navarro = NavarroRepairDecoder::decode(crate::repair_bytes(&text).unwrap()).unwrap();
+            let native = repair_bytes(&text);
+
+            let mut navarro_out = vec![];
+            let mut native_out = vec![];
+            navarro.write_source_string(&mut navarro_out).unwrap();
+            native.write_source_string(&mut native_out).unwrap();
+
+            assert_eq!(
+                navarro_out, native_out,
+                "Native and Navarro RePair grammars expanding to different texts"
+            );
+        }
+    }
+}